@@ -1,5 +1,9 @@
+use std::fmt::Write;
+
 use crate::color::{Color, RGBColorU8};
 
+pub const RESET: &str = "\x1b[0m";
+
 pub fn convert(color: Color, truergb: bool) -> ansi_term::Color {
     let rgb: RGBColorU8 = color.into();
     if truergb {
@@ -9,3 +13,82 @@ pub fn convert(color: Color, truergb: bool) -> ansi_term::Color {
         ansi_term::Color::Fixed(i)
     }
 }
+
+/// Keeps track of the colors currently set in the terminal, so that SGR sequences are only emitted when they change. \
+/// A color of None is the terminal's default color.
+#[derive(Debug, Default)]
+pub struct SgrEmitter {
+    fore: Option<RGBColorU8>,
+    back: Option<RGBColorU8>,
+}
+
+impl SgrEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The fore color currently set
+    pub fn fore(&self) -> Option<RGBColorU8> {
+        self.fore
+    }
+
+    /// The back color currently set
+    pub fn back(&self) -> Option<RGBColorU8> {
+        self.back
+    }
+
+    /// Writes a single SGR sequence that changes the colors which differ from the current state, nothing if neither changed.
+    pub fn set<W: Write>(
+        &mut self,
+        out: &mut W,
+        fore: Option<RGBColorU8>,
+        back: Option<RGBColorU8>,
+    ) -> std::fmt::Result {
+        let fore_changed = fore != self.fore;
+        let back_changed = back != self.back;
+        if !fore_changed && !back_changed {
+            return Ok(());
+        }
+
+        out.write_str("\x1b[")?;
+        if fore_changed {
+            match fore {
+                Some(c) => write!(out, "38;2;{};{};{}", c.r, c.g, c.b)?,
+                None => out.write_str("39")?,
+            }
+        }
+        if back_changed {
+            if fore_changed {
+                out.write_char(';')?;
+            }
+            match back {
+                Some(c) => write!(out, "48;2;{};{};{}", c.r, c.g, c.b)?,
+                None => out.write_str("49")?,
+            }
+        }
+        out.write_char('m')?;
+
+        self.fore = fore;
+        self.back = back;
+        Ok(())
+    }
+
+    /// Only changes the back color, keeping whatever fore color is set. Used for cells without a visible glyph.
+    pub fn set_back<W: Write>(
+        &mut self,
+        out: &mut W,
+        back: Option<RGBColorU8>,
+    ) -> std::fmt::Result {
+        self.set(out, self.fore, back)
+    }
+
+    /// Resets the terminal to its default colors, if any color is currently set.
+    pub fn reset<W: Write>(&mut self, out: &mut W) -> std::fmt::Result {
+        if self.fore.is_some() || self.back.is_some() {
+            out.write_str(RESET)?;
+            self.fore = None;
+            self.back = None;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Expansion of the sources of a batch into files, and naming of their outputs.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{anyhow, bail};
+use image::ImageFormat;
+use walkdir::WalkDir;
+
+use crate::{fetch::is_url, utils};
+
+/// Template of the output file names, where {ext} is the extension of the output format
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{ext}";
+
+/// Whether the source is a glob pattern rather than a path
+pub fn is_pattern(source: &str) -> bool {
+    source.contains(['*', '?', '['])
+}
+
+/// Expands each source into the files it refers to, in order. Globs are matched, and directories are listed (recursively if set).
+/// When images_only is set, files in directories are only kept when their extension is a supported image format. \
+/// `-` & urls are kept as is. Sources which could not be expanded become errors, so that they can be reported with the rest.
+pub fn expand_sources(
+    sources: &[String],
+    recursive: bool,
+    images_only: bool,
+) -> Vec<anyhow::Result<String>> {
+    let mut expanded = Vec::new();
+    for source in sources {
+        if source == "-" || is_url(source) {
+            expanded.push(Ok(source.clone()));
+            continue;
+        }
+
+        let path = utils::expand_path(source);
+        if Path::new(&path).is_dir() {
+            let files = list_dir(&path, recursive, images_only);
+            if files.is_empty() {
+                expanded.push(Err(anyhow!("No files found in directory '{}'", source)));
+            }
+            expanded.extend(files.into_iter().map(Ok));
+        } else if is_pattern(&path) {
+            match glob::glob(&path) {
+                Ok(paths) => {
+                    let before = expanded.len();
+                    for entry in paths {
+                        match entry {
+                            Ok(p) if p.is_file() => {
+                                expanded.push(Ok(p.to_string_lossy().to_string()))
+                            }
+                            Ok(_) => {}
+                            Err(e) => expanded.push(Err(e.into())),
+                        }
+                    }
+                    if expanded.len() == before {
+                        expanded.push(Err(anyhow!("No files matched '{}'", source)));
+                    }
+                }
+                Err(e) => expanded.push(Err(anyhow!("Invalid pattern '{}': {}", source, e))),
+            }
+        } else {
+            expanded.push(Ok(path));
+        }
+    }
+    expanded
+}
+
+/// Options which take a value, read from the usage line of the help such as `[-w <width>]`.
+/// Short options are also added by their long name, which is the name of their value.
+pub fn value_options(usage: &str) -> HashSet<String> {
+    let words: Vec<&str> = usage.split_whitespace().collect();
+    let mut options = HashSet::new();
+    for pair in words.windows(2) {
+        let (Some(option), Some(value)) = (pair[0].strip_prefix("[-"), pair[1].strip_prefix('<'))
+        else {
+            continue;
+        };
+        options.insert(format!("-{}", option));
+        let value = value.trim_end_matches([']', '>', '.']);
+        if !option.starts_with('-') {
+            options.insert(format!("--{}", value));
+        }
+    }
+    options
+}
+
+/// A lone `-` is read as an unknown option, so a `-` source is moved after `--`, where it is read as a positional. \
+/// The value of an option in value_options is kept as is, even when it is `-`.
+pub fn move_stdin_source(args: &[String], value_options: &HashSet<String>) -> Vec<String> {
+    let mut moved = Vec::with_capacity(args.len() + 1);
+    let mut stdin = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            moved.push(arg.clone());
+            moved.extend(args.cloned());
+            break;
+        }
+        if arg == "-" {
+            stdin = true;
+            continue;
+        }
+        moved.push(arg.clone());
+        if value_options.contains(arg) {
+            moved.extend(args.next().cloned());
+        }
+    }
+    if stdin {
+        match moved.iter().position(|x| x == "--") {
+            Some(i) => moved.insert(i + 1, "-".to_string()),
+            None => moved.extend(["--".to_string(), "-".to_string()]),
+        }
+    }
+    moved
+}
+
+/// Files of the directory sorted by name, including those in subdirectories when recursive
+fn list_dir(dir: &str, recursive: bool, images_only: bool) -> Vec<String> {
+    WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| !images_only || ImageFormat::from_path(entry.path()).is_ok())
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .collect()
+}
+
+/// Path naming the source, where urls use their path without the query
+fn name_path(source: &str) -> &Path {
+    if is_url(source) {
+        Path::new(
+            source
+                .split(['?', '#'])
+                .next()
+                .unwrap_or(source)
+                .trim_end_matches('/'),
+        )
+    } else {
+        Path::new(source)
+    }
+}
+
+/// File name of the source without its extension. Urls use their last path segment, and stdin is named "stdin".
+pub fn source_stem(source: &str) -> String {
+    if source == "-" {
+        return "stdin".to_string();
+    }
+    if source.starts_with("data:") {
+        return "data".to_string();
+    }
+    name_path(source)
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "output".to_string())
+}
+
+/// File name of the source with its extension, see source_stem
+pub fn source_name(source: &str) -> String {
+    if source == "-" || source.starts_with("data:") {
+        return source_stem(source);
+    }
+    name_path(source)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "output".to_string())
+}
+
+/// Fills in the template. Valid placeholders are {stem} (source file name without extension), {ext} (extension of the output format)
+/// and {index} (position of the file in the batch, starting from 1).
+pub fn output_name(
+    template: &str,
+    source: &str,
+    index: usize,
+    ext: &str,
+) -> anyhow::Result<String> {
+    if template.contains(['/', '\\']) {
+        bail!(
+            "Output template '{}' must be a file name, not a path",
+            template
+        );
+    }
+    let placeholders = ["{stem}", "{ext}", "{index}"];
+    if placeholders
+        .iter()
+        .fold(template.to_string(), |rest, x| rest.replace(x, ""))
+        .contains(['{', '}'])
+    {
+        bail!("Unknown placeholder in output template '{}'", template);
+    }
+    Ok(template
+        .replace("{stem}", &source_stem(source))
+        .replace("{ext}", ext)
+        .replace("{index}", &index.to_string()))
+}
@@ -0,0 +1,191 @@
+//! On-disk cache of downloaded images & rendered outputs, by default under `<cache dir>/clir_rs`.
+//!
+//! Downloads are kept with their ETag & Last-Modified headers so that they can be revalidated instead of downloaded again.
+//! Outputs are keyed by a hash of the source bytes & every setting affecting the render.
+//! When the cache grows over its size limit, the least recently used entries are removed first.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+/// Default size limit of the cache, 100 MiB
+pub const DEFAULT_MAX_SIZE: u64 = 100 * 1024 * 1024;
+/// Largest rendered output kept in the cache, 16 MiB, so that capturing the output while it is written uses bounded memory
+pub const MAX_OUTPUT_SIZE: usize = 16 * 1024 * 1024;
+const FETCH_DIR: &str = "fetch";
+const OUTPUT_DIR: &str = "output";
+
+/// Downloaded data, with the headers needed to revalidate it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FetchedEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    #[serde(skip)]
+    pub bytes: Vec<u8>,
+}
+
+impl FetchedEntry {
+    /// Whether the server gave a way to revalidate the data, without which it is not worth caching
+    pub fn can_revalidate(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+/// Hex encoded sha256 of the parts. Each part is prefixed with its length, so that moving bytes between parts changes the hash.
+pub fn hash_key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    /// `<cache dir>/clir_rs`, None when the platform has no cache dir
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("clir_rs"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn fetch_paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = hash_key(&[url.as_bytes()]);
+        let dir = self.dir.join(FETCH_DIR);
+        (
+            dir.join(format!("{}.json", key)),
+            dir.join(format!("{}.bin", key)),
+        )
+    }
+
+    /// Previously downloaded data of the url. Entries which cannot be read are treated as missing.
+    pub fn get_fetched(&self, url: &str) -> Option<FetchedEntry> {
+        let (meta_path, data_path) = self.fetch_paths(url);
+        let mut entry: FetchedEntry = serde_json::from_slice(&fs::read(&meta_path).ok()?).ok()?;
+        if entry.url != url {
+            return None;
+        }
+        entry.bytes = read_touch(&data_path)?;
+        touch(&meta_path);
+        Some(entry)
+    }
+
+    pub fn put_fetched(&self, entry: &FetchedEntry) -> io::Result<()> {
+        let (meta_path, data_path) = self.fetch_paths(&entry.url);
+        write_atomic(&data_path, &entry.bytes)?;
+        write_atomic(&meta_path, &serde_json::to_vec(entry)?)
+    }
+
+    pub fn get_output(&self, key: &str) -> Option<Vec<u8>> {
+        read_touch(&self.dir.join(OUTPUT_DIR).join(key))
+    }
+
+    pub fn put_output(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        write_atomic(&self.dir.join(OUTPUT_DIR).join(key), bytes)
+    }
+
+    /// Total size of the cached files in bytes
+    pub fn size(&self) -> u64 {
+        self.files().iter().map(|(_, len, _)| len).sum()
+    }
+
+    /// Removes the least recently used files until the cache fits its size limit, returning the number of bytes removed
+    pub fn evict(&self) -> io::Result<u64> {
+        let mut files = self.files();
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort_by_key(|(_, _, used)| *used);
+
+        let mut removed = 0;
+        for (path, len, _) in files {
+            if size <= self.max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            size -= len;
+            removed += len;
+        }
+        Ok(removed)
+    }
+
+    /// Removes every cached entry
+    pub fn clear(&self) -> io::Result<()> {
+        for dir in [FETCH_DIR, OUTPUT_DIR] {
+            match fs::remove_dir_all(self.dir.join(dir)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Cached files with their size & last use
+    fn files(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        [FETCH_DIR, OUTPUT_DIR]
+            .iter()
+            .flat_map(|dir| WalkDir::new(self.dir.join(dir)).min_depth(1).max_depth(1))
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok().filter(|x| x.is_file())?;
+                let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((entry.into_path(), meta.len(), used))
+            })
+            .collect()
+    }
+}
+
+/// Marks the file as used, as the modification time is the last use for eviction
+fn touch(path: &Path) {
+    let _ = File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+}
+
+fn read_touch(path: &Path) -> Option<Vec<u8>> {
+    let bytes = fs::read(path).ok()?;
+    touch(path);
+    Some(bytes)
+}
+
+/// Writes to a temporary file first, so that concurrent readers never see a partially written entry
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
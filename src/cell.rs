@@ -3,7 +3,10 @@ pub const CELL_H: usize = 4;
 pub const CELL_LEN: usize = CELL_W * CELL_H;
 pub type CellPixels = [Color; CELL_LEN];
 
+use std::collections::BTreeMap;
+
 use image::Rgba;
+use serde::{Deserialize, Serialize};
 
 use crate::{color::Color, NearestOption};
 pub struct CellGrid {
@@ -22,12 +25,15 @@ impl CellGrid {
     pub fn len(&self) -> usize {
         self.cells.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
 
     pub fn as_image_bytes(&self) -> (Vec<Color>, usize, usize) {
         let im_w = self.width() * CELL_W;
         let im_h = self.height() * CELL_H;
 
-        let mut data: Vec<Color> = Vec::with_capacity((im_w * im_h) );
+        let mut data: Vec<Color> = Vec::with_capacity(im_w * im_h);
 
         for y in 0..im_h {
             for x in 0..im_w {
@@ -52,20 +58,36 @@ impl CellGrid {
         (data, im_w, im_h)
     }
 
-    pub fn save_as(&self, fp: &str) -> Result<(), image::ImageError> {
+    /// Converts the cells into an image, where each cell is CELL_W x CELL_H pixels
+    pub fn to_image(&self) -> image::DynamicImage {
         let (bytes, im_w, im_h) = self.as_image_bytes();
         let im: image::ImageBuffer<Rgba<f32>, _> =
             image::ImageBuffer::from_raw(im_w as u32, im_h as u32, bytemuck::cast_vec(bytes))
                 .unwrap();
-        let dyn_im = image::DynamicImage::from(im);
-        dyn_im.into_rgba8().save(fp)
+        image::DynamicImage::from(im)
+    }
+
+    pub fn save_as(&self, fp: &str) -> Result<(), image::ImageError> {
+        self.to_image().into_rgba8().save(fp)
     }
 }
 
-impl From<&image::Rgba32FImage> for CellGrid {
-    fn from(img: &image::Rgba32FImage) -> Self {
+impl TryFrom<&image::Rgba32FImage> for CellGrid {
+    type Error = String;
+
+    /// Fails when the image is smaller than a single cell of CELL_W x CELL_H pixels
+    fn try_from(img: &image::Rgba32FImage) -> Result<Self, Self::Error> {
         let cols = img.width() as usize / CELL_W;
         let rows = img.height() as usize / CELL_H;
+        if cols == 0 || rows == 0 {
+            return Err(format!(
+                "image of {}x{} pixels is smaller than a cell of {}x{} pixels",
+                img.width(),
+                img.height(),
+                CELL_W,
+                CELL_H
+            ));
+        }
 
         let mut cells_arrays: Vec<[[f32; 4]; 8]> = Vec::with_capacity(img.len());
         for y in 0..(rows) {
@@ -87,11 +109,11 @@ impl From<&image::Rgba32FImage> for CellGrid {
         }
         let cells: Vec<CellPixels> = bytemuck::cast_vec(cells_arrays);
         let height = cells.len() / cols;
-        Self {
+        Ok(Self {
             cells,
             width: cols,
             height,
-        }
+        })
     }
 }
 
@@ -107,8 +129,7 @@ pub fn compute_minmax_contrast(values: &CellPixels) -> (Color, Color) {
 
     // Hybrid approach where we use find the values closest to the darkest & lightest possible values (transparent & white)
     // This in theory should give us the colors with the biggest contrast
-    for i in 0..values.len() {
-        let ele = values[i];
+    for &ele in values.iter() {
         let dark_dist = ele.distance2(&Color::TRANSPARENT);
         let bright_dist = ele.distance2(&Color::WHITE);
 
@@ -141,10 +162,10 @@ pub fn compute_minmax_contrast(values: &CellPixels) -> (Color, Color) {
 pub fn cell_flatten_ab(val: &CellPixels, a: &Color, b: &Color) -> (CellPixels, u8) {
     let mut copy = *val;
     let mut mask: u8 = 0;
-    for p_index in 0..copy.len() {
-        let current = copy[p_index];
+    for (p_index, pixel) in copy.iter_mut().enumerate() {
+        let current = *pixel;
 
-        copy[p_index] = match Color::compare_nearest(&current, a, b) {
+        *pixel = match Color::compare_nearest(&current, a, b) {
             NearestOption::A => {
                 mask |= 2_u8.pow(p_index as u32);
                 *a
@@ -156,34 +177,57 @@ pub fn cell_flatten_ab(val: &CellPixels, a: &Color, b: &Color) -> (CellPixels, u
 }
 
 /// Round the pixel values in the cells to their two light & dark colors determined by minmax_contrast
-pub fn round_cells(cells: &mut Vec<CellPixels>) {
-    for i in 0..cells.len() {
-        let ele = &cells[i];
-        let (a, b) = compute_minmax_contrast(ele);
-        (cells[i], _) = cell_flatten_ab(ele, &a, &b);
+pub fn round_cells(cells: &mut [CellPixels]) {
+    for cell in cells.iter_mut() {
+        let (a, b) = compute_minmax_contrast(cell);
+        (*cell, _) = cell_flatten_ab(cell, &a, &b);
     }
 }
 
 /// Round the pixel values in the cells to two colors (a & b)
-pub fn round_cells_with_ab(cells: &mut Vec<CellPixels>, a: &Color, b: &Color) {
-    for i in 0..cells.len() {
-        let ele = &cells[i];
-        (cells[i], _) = cell_flatten_ab(ele, a, b);
+pub fn round_cells_with_ab(cells: &mut [CellPixels], a: &Color, b: &Color) {
+    for cell in cells.iter_mut() {
+        (*cell, _) = cell_flatten_ab(cell, a, b);
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ComputedCell {
     pub fore: Color,
     pub back: Color,
     pub bitmask: u8,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedCellGrid")]
 pub struct ComputedCellGrid {
     pub cells: Vec<ComputedCell>,
     width: usize,
     height: usize,
 }
 
+/// Deserialized grid whose size has not been checked against its cells yet
+#[derive(Deserialize)]
+struct UncheckedCellGrid {
+    cells: Vec<ComputedCell>,
+    width: usize,
+    height: usize,
+}
+
+impl TryFrom<UncheckedCellGrid> for ComputedCellGrid {
+    type Error = String;
+
+    fn try_from(grid: UncheckedCellGrid) -> Result<Self, Self::Error> {
+        let (width, height, len) = (grid.width, grid.height, grid.cells.len());
+        ComputedCellGrid::new(grid.cells, width, height).ok_or_else(|| {
+            format!(
+                "grid size {}x{} does not match its number of cells ({})",
+                width, height, len
+            )
+        })
+    }
+}
+
 impl CellGrid {
     pub fn compute(&self, invert: bool) -> ComputedCellGrid {
         ComputedCellGrid::create(self, None, None, invert)
@@ -196,15 +240,27 @@ impl CellGrid {
 
 impl ComputedCellGrid {
     pub const UTF8_BYTE_SIZE: usize = 4;
+
+    /// Creates a grid from cells ordered row by row. Returns None when width * height is not the number of cells
+    pub fn new(cells: Vec<ComputedCell>, width: usize, height: usize) -> Option<Self> {
+        if width.checked_mul(height) != Some(cells.len()) {
+            return None;
+        }
+        Some(Self {
+            cells,
+            width,
+            height,
+        })
+    }
+
     fn create(grid: &CellGrid, fore: Option<&Color>, back: Option<&Color>, invert: bool) -> Self {
         let computed_cells: Vec<ComputedCell> = grid
             .cells
             .iter()
             .map(|x| {
-                let (fore_, back_) = if fore.is_some() && back.is_some() {
-                    (*fore.unwrap(), *back.unwrap())
-                } else {
-                    compute_minmax_contrast(x)
+                let (fore_, back_) = match (fore, back) {
+                    (Some(fore), Some(back)) => (*fore, *back),
+                    _ => compute_minmax_contrast(x),
                 };
 
                 let (final_fore, final_back) = if invert {
@@ -229,11 +285,61 @@ impl ComputedCellGrid {
         }
     }
 
-
     pub fn width(&self) -> usize {
         self.width
     }
     pub fn height(&self) -> usize {
         self.height
     }
+
+    /// Most common color of the grid, from the fore & back colors weighted by the number of pixels they cover. \
+    /// Similar colors are grouped together, and the average of the largest group is returned. None when every cell is transparent.
+    pub fn dominant_color(&self) -> Option<Color> {
+        const LEVELS: f32 = 8.0;
+        let mut groups: BTreeMap<[u8; 3], (Color, u32)> = BTreeMap::new();
+        for cell in &self.cells {
+            let fore_pixels = cell.bitmask.count_ones();
+            for (color, pixels) in [
+                (cell.fore, fore_pixels),
+                (cell.back, CELL_LEN as u32 - fore_pixels),
+            ] {
+                if pixels == 0 || color.a < 0.5 {
+                    continue;
+                }
+                let key = [color.r, color.g, color.b]
+                    .map(|c| (c.clamp(0.0, 1.0) * (LEVELS - 1.0)).round() as u8);
+                let group = groups.entry(key).or_insert((Color::TRANSPARENT, 0));
+                group.0 = group.0 + color * pixels as f32;
+                group.1 += pixels;
+            }
+        }
+        groups
+            .into_values()
+            .max_by_key(|(_, pixels)| *pixels)
+            .map(|(sum, pixels)| sum / pixels as f32)
+    }
+}
+
+impl ComputedCellGrid {
+    /// Reconstructs the cell pixels from the computed cells, where each pixel is the fore color if its bit is set in the bitmask, else the back color.
+    pub fn to_cell_grid(&self) -> CellGrid {
+        let cells = self
+            .cells
+            .iter()
+            .map(|cell| {
+                let mut pixels: CellPixels = [cell.back; CELL_LEN];
+                for (p_index, pixel) in pixels.iter_mut().enumerate() {
+                    if cell.bitmask & (1 << p_index) != 0 {
+                        *pixel = cell.fore;
+                    }
+                }
+                pixels
+            })
+            .collect();
+        CellGrid {
+            cells,
+            width: self.width(),
+            height: self.height(),
+        }
+    }
 }
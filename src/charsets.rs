@@ -58,3 +58,18 @@ pub fn cell_bitmask_to_char_index(bitmask_: u8) -> u8 {
 
     // result.reverse_bits()
 }
+
+/// Converts a char set index back to a cell bitmask. This is the inverse of cell_bitmask_to_char_index
+pub fn char_index_to_cell_bitmask(index: u8) -> u8 {
+    let mut result = 0;
+
+    // The last & first 3 bits are at the same position in both orderings
+    result |= index & 0b11100001;
+
+    // Undo each move done by cell_bitmask_to_char_index
+    result |= (index & 0b00000100) << 2;
+    result |= (index & 0b00010000) >> 1;
+    result |= (index & 0b00000010) << 1;
+    result |= (index & 0b00001000) >> 2;
+    result
+}
@@ -1,9 +1,12 @@
 use std::ops;
 
+use serde::{Deserialize, Serialize};
+
 use crate::NearestOption;
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f32; 4]", into = "[f32; 4]")]
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -45,6 +48,11 @@ impl RGBColorU8{
         x |= 255;
         x
     }
+
+    /// Formats the color as a css style hex string, eg. `#ff00aa`
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
 }
 
 
@@ -69,6 +77,37 @@ impl Color {
         a: 0.0,
     };
 
+    /// Parses a css style hex color (`#rgb`, `#rrggbb` or `#rrggbbaa`, the `#` is optional), or one of "black", "white" & "transparent"
+    pub fn parse(s: &str) -> Option<Color> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "black" => return Some(Color::BLACK),
+            "white" => return Some(Color::WHITE),
+            "transparent" | "none" => return Some(Color::TRANSPARENT),
+            _ => {}
+        }
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize, len: usize| -> Option<f32> {
+            let v = u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok()?;
+            Some(if len == 1 { v * 17 } else { v } as f32 / 255.0)
+        };
+        let (len, a) = match hex.len() {
+            3 => (1, 1.0),
+            6 => (2, 1.0),
+            8 => (2, channel(3, 2)?),
+            _ => return None,
+        };
+        Some(Color {
+            r: channel(0, len)?,
+            g: channel(1, len)?,
+            b: channel(2, len)?,
+            a,
+        })
+    }
+
     pub fn mag2(&self) -> f32{
         self.r * self.r +
         self.g * self.g +
@@ -111,6 +150,17 @@ impl Color {
     }
 }
 
+impl From<[f32; 4]> for Color {
+    fn from([r, g, b, a]: [f32; 4]) -> Self {
+        Self { r, g, b, a }
+    }
+}
+
+impl From<Color> for [f32; 4] {
+    fn from(val: Color) -> Self {
+        [val.r, val.g, val.b, val.a]
+    }
+}
 
 impl ops::Sub<Color> for Color{
     type Output = Self;
@@ -0,0 +1,214 @@
+//! User configuration, so that the same options do not have to be given on every invocation.
+//!
+//! Settings are merged with the precedence: command line > `CLIR_*` environment variables > preset > config file > built-in defaults.
+//! The config file sets defaults at its top level, and named presets in `[presets.<name>]` tables, eg.
+//!
+//! ```toml
+//! charset = "classic"
+//! scaling = "gaussian"
+//!
+//! [presets.logo]
+//! width = 40
+//! transparency-t = 0.5
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the environment variables overriding settings, eg. CLIR_TRANSPARENCY_T
+pub const ENV_PREFIX: &str = "CLIR_";
+/// Environment variable selecting the preset when --preset is not given
+pub const PRESET_ENV: &str = "CLIR_PRESET";
+
+/// Declares Settings with one optional field per setting, so that the list of settings is only written once
+macro_rules! settings {
+    ($($field:ident: $ty:ty,)*) => {
+        /// Options which can be set in the config file, presets & environment variables.
+        /// Keys are the names of the command line options, eg. `transparency-t`. A setting of None is not set.
+        #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        pub struct Settings {
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$ty>,
+            )*
+        }
+
+        impl Settings {
+            /// Field names of all settings
+            pub const FIELDS: &'static [&'static str] = &[$(stringify!($field),)*];
+
+            /// Sets the field from its text, parsed as the type of the field
+            fn set_from_str(&mut self, field: &str, value: &str) -> anyhow::Result<()> {
+                match field {
+                    $(stringify!($field) => {
+                        self.$field = Some(value.trim().parse::<$ty>().map_err(|e| anyhow!("{}", e))?)
+                    })*
+                    _ => bail!("Unknown setting {}", field),
+                }
+                Ok(())
+            }
+
+            /// Combines the settings, where the settings of over take precedence
+            pub fn merge(self, over: Settings) -> Settings {
+                Settings {
+                    $($field: over.$field.or(self.$field),)*
+                }
+            }
+        }
+    };
+}
+
+settings! {
+    width: usize,
+    height: usize,
+    no_autosize: bool,
+    no_keep_aspect: bool,
+    no_color: bool,
+    plain_text: bool,
+    use_original_image_size: bool,
+    charset: String,
+    scaling: String,
+    transparency_t: f32,
+    invert_cell: bool,
+    format: String,
+    svg_font_family: String,
+    svg_font_size: f32,
+    svg_cell_width: f32,
+    svg_line_height: f32,
+    png_scale: u32,
+    sauce_title: String,
+    sauce_author: String,
+    sauce_group: String,
+    sauce_font: String,
+    irc_line_limit: usize,
+    codegen_name: String,
+    codegen_lines: bool,
+    kitty_format: String,
+    passthrough: String,
+    output_template: String,
+    recursive: bool,
+    timeout: f32,
+    connect_timeout: f32,
+    read_timeout: f32,
+    max_download: String,
+    user_agent: String,
+    no_cache: bool,
+    cache_size: String,
+    max_image_size: String,
+    max_decoded_size: String,
+    fit: String,
+    gravity: String,
+    background: String,
+    crop: String,
+    rotate: u32,
+    flip: String,
+    trim: bool,
+    trim_tolerance: f32,
+    trim_padding: u32,
+    cell_aspect: String,
+    align: String,
+    valign: String,
+    margin: String,
+    padding: String,
+    padding_color: String,
+    border: String,
+    border_color: String,
+    title: String,
+    caption: String,
+}
+
+impl Settings {
+    /// Parses settings from toml
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Reads the settings from `CLIR_*` environment variables, given as (name, value) pairs. \
+    /// Values are parsed as the type of their setting, eg. `0.5` for a number & `true` or `false` for a switch.
+    pub fn from_env_vars<I: IntoIterator<Item = (String, String)>>(
+        vars: I,
+    ) -> anyhow::Result<Self> {
+        let mut settings = Settings::default();
+        for (name, value) in vars {
+            let Some(field) = name.strip_prefix(ENV_PREFIX).and_then(|x| {
+                Settings::FIELDS
+                    .iter()
+                    .find(|field| field.eq_ignore_ascii_case(x))
+            }) else {
+                continue;
+            };
+            settings
+                .set_from_str(field, &value)
+                .with_context(|| format!("Invalid value '{}' for {}", value, name))?;
+        }
+        Ok(settings)
+    }
+
+    /// Reads the settings from the `CLIR_*` variables of the environment
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_vars(std::env::vars())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+/// Contents of the config file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    pub defaults: Settings,
+    pub presets: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut table: toml::Table = toml::from_str(text)?;
+        let presets = match table.remove("presets") {
+            Some(presets) => presets.try_into().context("Invalid presets")?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            defaults: Settings::deserialize(table)?,
+            presets,
+        })
+    }
+
+    /// Reads the config file. A missing file is an empty config.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).with_context(|| format!("Invalid config file {:?}", path))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read config file {:?}", path)),
+        }
+    }
+
+    /// Settings of the config file with the preset applied
+    pub fn settings(&self, preset: Option<&str>) -> anyhow::Result<Settings> {
+        let Some(name) = preset else {
+            return Ok(self.defaults.clone());
+        };
+        match self.presets.get(name) {
+            Some(preset) => Ok(self.defaults.clone().merge(preset.clone())),
+            None => bail!(
+                "Unknown preset '{}'. Available presets are {:?}",
+                name,
+                self.presets.keys().collect::<Vec<_>>()
+            ),
+        }
+    }
+}
+
+/// Default location of the config file, `<config dir>/clir_rs/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("clir_rs").join("config.toml"))
+}
@@ -0,0 +1,272 @@
+//! Fetching of sources given as urls: http(s) downloads, `data:` uris & `file://` urls.
+
+use std::{
+    fs, io,
+    io::Read,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use data_url::DataUrl;
+use reqwest::{
+    blocking::Client,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+        LAST_MODIFIED,
+    },
+    redirect, StatusCode,
+};
+use url::Url;
+
+use crate::{
+    cache::{Cache, FetchedEntry},
+    limits::LimitError,
+};
+
+/// Default maximum size of a download, 50 MiB
+pub const DEFAULT_MAX_SIZE: u64 = 50 * 1024 * 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_USER_AGENT: &str = concat!("clir_rs/", env!("CARGO_PKG_VERSION"));
+/// Maximum number of redirects followed
+pub const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct FetchSettings {
+    pub connect_timeout: Duration,
+    /// Maximum time of the whole request, including reading the body
+    pub timeout: Duration,
+    /// Maximum time waiting for the response or for more of the body, so that a stalled download fails early
+    pub read_timeout: Duration,
+    /// Maximum size of the fetched data in bytes
+    pub max_size: u64,
+    pub user_agent: String,
+    /// Extra request headers as (name, value)
+    pub headers: Vec<(String, String)>,
+    /// Cache of downloads, revalidated with their ETag & Last-Modified headers. None always downloads.
+    pub cache: Option<Cache>,
+}
+
+impl Default for FetchSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            max_size: DEFAULT_MAX_SIZE,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: Vec::new(),
+            cache: None,
+        }
+    }
+}
+
+/// Whether the source is a url which can be fetched, rather than a path
+pub fn is_url(source: &str) -> bool {
+    Url::parse(source).is_ok_and(|url| match url.scheme() {
+        "http" | "https" => url.has_host(),
+        "data" | "file" => true,
+        _ => false,
+    })
+}
+
+/// Parses a `Name: value` header given on the command line
+pub fn parse_header(header: &str) -> anyhow::Result<(String, String)> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid header '{}', expected 'Name: value'", header))?;
+    let name = name.trim();
+    HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| anyhow!("Invalid header name '{}'", name))?;
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix (powers of 1024), eg. `20M`
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let size = size.trim();
+    let (digits, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => bail!("Invalid size '{}', expected eg. 500K or 20M", size),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("Invalid size '{}', expected eg. 500K or 20M", size))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("Size '{}' is too large", size))
+}
+
+/// Fetches the bytes of the url. Errors when the data is larger than the size limit, or is a web page instead of an image.
+pub fn fetch(source: &str, settings: &FetchSettings) -> anyhow::Result<Vec<u8>> {
+    let url = Url::parse(source).with_context(|| format!("Invalid url '{}'", source))?;
+    let (bytes, content_type) = match url.scheme() {
+        "data" => fetch_data(source)?,
+        "file" => {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file url '{}'", source))?;
+            let len = fs::metadata(&path)
+                .with_context(|| format!("Failed to read {:?}", path))?
+                .len();
+            check_size(len, settings.max_size)?;
+            (fs::read(&path)?, None)
+        }
+        "http" | "https" => {
+            let (entry, fresh) = fetch_http(url, settings)?;
+            check_size(entry.bytes.len() as u64, settings.max_size)?;
+            check_content(&entry.bytes, entry.content_type.as_deref())?;
+            // Only kept once it is known to be an image, so that error pages are not cached
+            if let (true, Some(cache)) = (fresh && entry.can_revalidate(), &settings.cache) {
+                if let Err(e) = cache.put_fetched(&entry) {
+                    eprintln!("Warning: failed to cache download of '{}': {}", source, e);
+                }
+            }
+            return Ok(entry.bytes);
+        }
+        scheme => bail!("Unsupported url scheme '{}'", scheme),
+    };
+    check_size(bytes.len() as u64, settings.max_size)?;
+    check_content(&bytes, content_type.as_deref())?;
+    Ok(bytes)
+}
+
+fn check_size(len: u64, max_size: u64) -> anyhow::Result<()> {
+    if len > max_size {
+        return Err(LimitError::Download { max: max_size }.into());
+    }
+    Ok(())
+}
+
+/// Decodes a `data:` uri, returning its bytes & media type
+fn fetch_data(source: &str) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+    let data = DataUrl::process(source).map_err(|e| anyhow!("Invalid data uri: {:?}", e))?;
+    let mime = data.mime_type();
+    let content_type = format!("{}/{}", mime.type_, mime.subtype);
+    let (bytes, _) = data
+        .decode_to_vec()
+        .map_err(|_| anyhow!("Invalid base64 in data uri"))?;
+    Ok((bytes, Some(content_type)))
+}
+
+/// Downloads the url, or revalidates the cached download of it. Returns whether the data was downloaded rather than cached.
+fn fetch_http(url: Url, settings: &FetchSettings) -> anyhow::Result<(FetchedEntry, bool)> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &settings.headers {
+        headers.append(
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow!("Invalid header name '{}'", name))?,
+            HeaderValue::from_str(value)
+                .map_err(|_| anyhow!("Invalid value of header '{}'", name))?,
+        );
+    }
+    // The timeout of the blocking client applies to each wait, for the response & for each read of the body
+    let deadline = Instant::now() + settings.timeout;
+    let client = Client::builder()
+        .connect_timeout(settings.connect_timeout)
+        .timeout(settings.read_timeout.min(settings.timeout))
+        .redirect(redirect::Policy::limited(MAX_REDIRECTS))
+        .user_agent(settings.user_agent.as_str())
+        .default_headers(headers)
+        .build()?;
+
+    let cached = settings
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.get_fetched(url.as_str()));
+    let mut request = client.get(url.clone());
+    if let Some(entry) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let res = request.send()?;
+    if let (StatusCode::NOT_MODIFIED, Some(entry)) = (res.status(), cached) {
+        return Ok((entry, false));
+    }
+    let res = res.error_for_status()?;
+    if let Some(len) = res.content_length() {
+        check_size(len, settings.max_size)?;
+    }
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|x: &HeaderValue| x.to_str().ok())
+            .map(|x| x.to_string())
+    };
+    let mut entry = FetchedEntry {
+        url: url.to_string(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+        content_type: header(CONTENT_TYPE),
+        bytes: Vec::new(),
+    };
+
+    // Reads one byte past the limit, so that a larger body is detected without reading all of it
+    DeadlineReader {
+        inner: res,
+        deadline,
+        timeout: settings.timeout,
+    }
+    .take(settings.max_size + 1)
+    .read_to_end(&mut entry.bytes)?;
+    Ok((entry, true))
+}
+
+/// Reader failing once the deadline passes, so that a body sent slowly cannot take longer than the timeout
+struct DeadlineReader<R> {
+    inner: R,
+    deadline: Instant,
+    timeout: Duration,
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if Instant::now() > self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("The download took longer than {:?}", self.timeout),
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Whether the data looks like an html page, eg. an error or login page returned instead of the image
+fn is_html(bytes: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_ascii_lowercase();
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    start.starts_with("<!doctype html") || start.starts_with("<html") || start.starts_with("<head")
+}
+
+/// Checks that the fetched data is an image, using the content type when it is known and otherwise the data itself
+fn check_content(bytes: &[u8], content_type: Option<&str>) -> anyhow::Result<()> {
+    if image::guess_format(bytes).is_ok() {
+        return Ok(());
+    }
+    let media_type = content_type
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_ascii_lowercase());
+    if media_type.as_deref() == Some("text/html") || is_html(bytes) {
+        bail!(
+            "The url returned a web page (text/html) instead of an image. Use the direct link to the image instead"
+        );
+    }
+    match media_type {
+        Some(media_type) if !media_type.starts_with("image/") => {
+            bail!("The url returned '{}' instead of an image", media_type)
+        }
+        // Left for the decoder to report
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,170 @@
+//! How the image is fitted into the output box of width x height cells.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::color::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitMode {
+    /// Shrinks the width or height of the output to the aspect ratio of the image
+    Keep,
+    /// Scales the image to fit inside the box, padding the rest with the background color
+    Contain,
+    /// Scales the image to fill the box, cropping the overflow around the gravity
+    Cover,
+    /// Stretches the image to the box
+    Fill,
+}
+
+/// Looks up the fit mode by name, None for unknown values so that the default can be picked
+pub fn get_fit_mode(name: &str) -> Option<FitMode> {
+    match name {
+        "keep" => Some(FitMode::Keep),
+        "contain" => Some(FitMode::Contain),
+        "cover" => Some(FitMode::Cover),
+        "fill" => Some(FitMode::Fill),
+        _ => None,
+    }
+}
+
+/// Part of the image kept by FitMode::Cover, as the focal point in fractions of the image's width & height.
+/// The crop is centered on the point where possible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gravity {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Gravity {
+    pub const CENTER: Gravity = Gravity { x: 0.5, y: 0.5 };
+}
+
+/// Parses the gravity from a name ("center", "top", "bottom", "left", "right" & corners such as "top-left"),
+/// or a focal point "x,y" in fractions of the image, eg. "0.3,0.25". Uses the center for unknown values.
+pub fn get_gravity(name: &str) -> Gravity {
+    if let Some((x, y)) = name.split_once(',') {
+        return match (x.trim().parse::<f32>(), y.trim().parse::<f32>()) {
+            (Ok(x), Ok(y)) => Gravity {
+                x: x.clamp(0.0, 1.0),
+                y: y.clamp(0.0, 1.0),
+            },
+            _ => Gravity::CENTER,
+        };
+    }
+    let mut gravity = Gravity::CENTER;
+    for part in name.split('-') {
+        match part {
+            "top" => gravity.y = 0.0,
+            "bottom" => gravity.y = 1.0,
+            "left" => gravity.x = 0.0,
+            "right" => gravity.x = 1.0,
+            _ => {}
+        }
+    }
+    gravity
+}
+
+/// Everything fitting the image needs besides the size, so that the cells & the graphics outputs show the same part of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    pub mode: FitMode,
+    pub gravity: Gravity,
+    pub background: Color,
+    pub filter: FilterType,
+}
+
+impl Fit {
+    /// Fits the image into width x height pixels, see fit_image
+    pub fn apply(
+        &self,
+        img: &DynamicImage,
+        width: u32,
+        height: u32,
+        pixel_aspect: f32,
+    ) -> DynamicImage {
+        fit_image(
+            img,
+            width,
+            height,
+            self.mode,
+            self.gravity,
+            self.background,
+            pixel_aspect,
+            self.filter,
+        )
+    }
+}
+
+/// Offset of a crop of size len out of total, centered on the focal point but kept inside the image
+fn crop_offset(total: u32, len: u32, focus: f32) -> u32 {
+    let center = total as f32 * focus;
+    (center - len as f32 / 2.0)
+        .round()
+        .clamp(0.0, total.saturating_sub(len) as f32) as u32
+}
+
+/// Resizes the image to exactly width x height pixels with the fit mode.
+/// FitMode::Keep expects the size to already have the aspect ratio of the image, so it is resized like FitMode::Fill.
+///
+/// pixel_aspect is the width / height of each output pixel once displayed, eg. 1.1 when the terminal's cells are 2.2 times taller than wide,
+/// so that contain & cover keep the image's proportions on screen.
+#[allow(clippy::too_many_arguments)]
+pub fn fit_image(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    mode: FitMode,
+    gravity: Gravity,
+    background: Color,
+    pixel_aspect: f32,
+    filter: FilterType,
+) -> DynamicImage {
+    let (iw, ih) = img.dimensions();
+    if iw == 0 || ih == 0 || width == 0 || height == 0 {
+        return img.resize_exact(width, height, filter);
+    }
+    // Width of the source measured in output pixels, so that scaling both axes by the same amount keeps its proportions on screen
+    let pixel_aspect = if pixel_aspect.is_finite() && pixel_aspect > 0.0 {
+        pixel_aspect as f64
+    } else {
+        1.0
+    };
+    let virtual_width = iw as f64 / pixel_aspect;
+    let scale_x = width as f64 / virtual_width;
+    let scale_y = height as f64 / ih as f64;
+
+    match mode {
+        FitMode::Keep | FitMode::Fill => img.resize_exact(width, height, filter),
+        FitMode::Contain => {
+            let scale = scale_x.min(scale_y);
+            let w = ((virtual_width * scale).round() as u32).clamp(1, width);
+            let h = ((ih as f64 * scale).round() as u32).clamp(1, height);
+            let resized = img.resize_exact(w, h, filter).into_rgba8();
+
+            let fill = Rgba([
+                (background.r * 255.0).round() as u8,
+                (background.g * 255.0).round() as u8,
+                (background.b * 255.0).round() as u8,
+                (background.a * 255.0).round() as u8,
+            ]);
+            let mut canvas = RgbaImage::from_pixel(width, height, fill);
+            image::imageops::overlay(
+                &mut canvas,
+                &resized,
+                ((width - w) / 2) as i64,
+                ((height - h) / 2) as i64,
+            );
+            DynamicImage::ImageRgba8(canvas)
+        }
+        FitMode::Cover => {
+            // The crop of the source with the aspect ratio of the box is cut first, so that only the kept part is resized
+            let scale = scale_x.max(scale_y);
+            let cw = ((width as f64 * pixel_aspect / scale).round() as u32).clamp(1, iw);
+            let ch = ((height as f64 / scale).round() as u32).clamp(1, ih);
+            let x = crop_offset(iw, cw, gravity.x);
+            let y = crop_offset(ih, ch, gravity.y);
+            img.crop_imm(x, y, cw, ch)
+                .resize_exact(width, height, filter)
+        }
+    }
+}
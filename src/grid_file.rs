@@ -0,0 +1,140 @@
+//! Saving & loading of computed grids, so that they can be rendered again later without decoding the source image.
+//!
+//! Grids are stored either as json, or as a compact binary form: MAGIC, BINARY_VERSION, the width & height as little endian u32,
+//! then each cell as its fore & back colors in 8 bit RGBA followed by its bitmask, 9 bytes per cell.
+//! The binary form keeps 8 bits per channel, the precision every output is drawn with, while json keeps the exact f32 colors.
+//!
+//! Saved grids are re-rendered in any output format, but always in 24 bit color: lower color depths are out of scope.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    cell::{ComputedCell, ComputedCellGrid},
+    color::Color,
+};
+
+pub const MAGIC: &[u8; 8] = b"CLIRGRID";
+pub const BINARY_VERSION: u8 = 2;
+/// Bytes of each cell in the binary form
+pub const CELL_BYTES: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridFormat {
+    Json,
+    Binary,
+}
+
+/// Picks the format from the file extension. `.json` files are saved as json, everything else as binary.
+pub fn get_grid_format(path: &str) -> GridFormat {
+    match Path::new(path).extension().and_then(|x| x.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("json") => GridFormat::Json,
+        _ => GridFormat::Binary,
+    }
+}
+
+pub fn write_grid<W: Write>(
+    out: &mut W,
+    grid: &ComputedCellGrid,
+    format: GridFormat,
+) -> anyhow::Result<()> {
+    match format {
+        GridFormat::Json => serde_json::to_writer(out, grid)?,
+        GridFormat::Binary => {
+            out.write_all(MAGIC)?;
+            out.write_all(&[BINARY_VERSION])?;
+            out.write_all(&(grid.width() as u32).to_le_bytes())?;
+            out.write_all(&(grid.height() as u32).to_le_bytes())?;
+            let mut data = Vec::with_capacity(grid.cells.len() * CELL_BYTES);
+            for cell in &grid.cells {
+                data.extend_from_slice(&color_to_rgba(cell.fore));
+                data.extend_from_slice(&color_to_rgba(cell.back));
+                data.push(cell.bitmask);
+            }
+            out.write_all(&data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a grid written by write_grid. The format is detected from the data.
+pub fn read_grid<R: Read>(input: &mut R) -> anyhow::Result<ComputedCellGrid> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+
+    match bytes.strip_prefix(MAGIC) {
+        Some([version, data @ ..]) => {
+            if *version != BINARY_VERSION {
+                bail!(
+                    "Unsupported grid version {}, expected {}",
+                    version,
+                    BINARY_VERSION
+                );
+            }
+            decode_binary(data)
+        }
+        Some([]) => bail!("Grid data is truncated"),
+        None => serde_json::from_slice(&bytes).context("Data is neither a binary nor a json grid"),
+    }
+}
+
+fn color_to_rgba(color: Color) -> [u8; 4] {
+    [color.r, color.g, color.b, color.a].map(|x| (x * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+fn rgba_to_color(rgba: &[u8]) -> Color {
+    Color {
+        r: rgba[0] as f32 / 255.0,
+        g: rgba[1] as f32 / 255.0,
+        b: rgba[2] as f32 / 255.0,
+        a: rgba[3] as f32 / 255.0,
+    }
+}
+
+/// Decodes the binary form after its MAGIC & version
+fn decode_binary(data: &[u8]) -> anyhow::Result<ComputedCellGrid> {
+    let (Some(width), Some(height)) = (data.get(0..4), data.get(4..8)) else {
+        bail!("Grid data is truncated");
+    };
+    let width = u32::from_le_bytes(width.try_into()?) as usize;
+    let height = u32::from_le_bytes(height.try_into()?) as usize;
+    let cells = &data[8..];
+    if width
+        .checked_mul(height)
+        .and_then(|x| x.checked_mul(CELL_BYTES))
+        != Some(cells.len())
+    {
+        bail!(
+            "Grid size {}x{} does not match its {} bytes of cells",
+            width,
+            height,
+            cells.len()
+        );
+    }
+    let cells = cells
+        .chunks_exact(CELL_BYTES)
+        .map(|cell| ComputedCell {
+            fore: rgba_to_color(&cell[0..4]),
+            back: rgba_to_color(&cell[4..8]),
+            bitmask: cell[8],
+        })
+        .collect();
+    // The size was checked against the cells above
+    Ok(ComputedCellGrid::new(cells, width, height).unwrap())
+}
+
+pub fn save_grid(path: &str, grid: &ComputedCellGrid) -> anyhow::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_grid(&mut file, grid, get_grid_format(path))?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn load_grid(path: &str) -> anyhow::Result<ComputedCellGrid> {
+    read_grid(&mut BufReader::new(File::open(path)?))
+}
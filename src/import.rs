@@ -0,0 +1,219 @@
+//! Imports existing unicode art (plain braille/block text, optionally colored with SGR sequences) back into a ComputedCellGrid.
+
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+use crate::{
+    cell::{ComputedCell, ComputedCellGrid, CELL_H, CELL_W},
+    charsets,
+    color::Color,
+    outputs::bitmap_font::{self, GLYPH_H, GLYPH_W},
+};
+
+/// Fore color of cells that were not colored by an SGR sequence
+pub const DEFAULT_FORE: Color = Color::WHITE;
+/// Back color of cells that were not colored by an SGR sequence, kept transparent so that it renders as the terminal's background
+pub const DEFAULT_BACK: Color = Color::TRANSPARENT;
+
+#[derive(Debug, PartialEq)]
+pub enum ImportWarnings {
+    None,
+    /// Number of characters whose bitmask could not be recovered. They were imported as empty cells.
+    UnknownCharacters(usize),
+}
+
+/// Parses unicode art into a grid. Each character becomes a cell, and lines shorter than the longest line are padded with empty cells.
+pub fn import_art(text: &str) -> (ComputedCellGrid, ImportWarnings) {
+    let lookup = glyph_lookup();
+    let mut unknown = 0;
+
+    let mut rows: Vec<Vec<ComputedCell>> = vec![Vec::new()];
+    let mut fore: Option<Color> = None;
+    let mut back: Option<Color> = None;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => parse_escape(&mut chars, &mut fore, &mut back),
+            '\n' => rows.push(Vec::new()),
+            '\r' => {}
+            _ => {
+                let bitmask = match c {
+                    '\t' => 0,
+                    _ => char_to_bitmask(&lookup, c).unwrap_or_else(|| {
+                        unknown += 1;
+                        0
+                    }),
+                };
+                rows.last_mut().unwrap().push(ComputedCell {
+                    fore: fore.unwrap_or(DEFAULT_FORE),
+                    back: back.unwrap_or(DEFAULT_BACK),
+                    bitmask,
+                });
+            }
+        }
+    }
+
+    // Trailing newlines do not start a new row
+    while rows.len() > 1 && rows.last().unwrap().is_empty() {
+        rows.pop();
+    }
+
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let height = if width == 0 { 0 } else { rows.len() };
+    let mut cells = Vec::with_capacity(width * height);
+    for mut row in rows.into_iter().take(height) {
+        row.resize(
+            width,
+            ComputedCell {
+                fore: DEFAULT_FORE,
+                back: DEFAULT_BACK,
+                bitmask: 0,
+            },
+        );
+        cells.append(&mut row);
+    }
+
+    (
+        ComputedCellGrid::new(cells, width, height).unwrap(),
+        if unknown > 0 {
+            ImportWarnings::UnknownCharacters(unknown)
+        } else {
+            ImportWarnings::None
+        },
+    )
+}
+
+/// Maps every character of the bundled charsets to its char set index
+fn glyph_lookup() -> HashMap<char, u8> {
+    let mut lookup = HashMap::new();
+    for charset in [charsets::BRAILLE, charsets::CLASSIC] {
+        for (index, c) in charset.chars().enumerate() {
+            lookup.insert(c, index as u8);
+        }
+    }
+    // The blank braille pattern is not part of the charsets, as a space is used instead
+    lookup.insert('\u{2800}', 0);
+    lookup
+}
+
+/// Recovers the cell bitmask of a character. Characters outside of the charsets are sampled from the bitmap font,
+/// where a pixel of the cell is set when most of its area in the glyph is covered.
+fn char_to_bitmask(lookup: &HashMap<char, u8>, c: char) -> Option<u8> {
+    if let Some(index) = lookup.get(&c) {
+        return Some(charsets::char_index_to_cell_bitmask(*index));
+    }
+
+    let glyph = bitmap_font::glyph(c)?;
+    let area_w = GLYPH_W / CELL_W as u32;
+    let area_h = GLYPH_H / CELL_H as u32;
+    let mut bitmask = 0;
+    for p_index in 0..(CELL_W * CELL_H) {
+        let area_x = (p_index % CELL_W) as u32 * area_w;
+        let area_y = (p_index / CELL_W) as u32 * area_h;
+        let covered = (area_y..area_y + area_h)
+            .flat_map(|y| (area_x..area_x + area_w).map(move |x| (x, y)))
+            .filter(|(x, y)| bitmap_font::is_set(&glyph, *x, *y))
+            .count();
+        if covered * 2 >= (area_w * area_h) as usize {
+            bitmask |= 1 << p_index;
+        }
+    }
+    Some(bitmask)
+}
+
+/// Consumes an escape sequence, applying it to the current colors if it is an SGR sequence. Other sequences are skipped.
+fn parse_escape(chars: &mut Peekable<Chars>, fore: &mut Option<Color>, back: &mut Option<Color>) {
+    match chars.next() {
+        Some('[') => {}
+        // OSC, such as hyperlinks & window titles, ends with BEL or ST
+        Some(']') => {
+            while let Some(c) = chars.next() {
+                if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+            return;
+        }
+        // Other sequences are the single character after ESC
+        _ => return,
+    }
+
+    let mut params = String::new();
+    let final_byte = loop {
+        match chars.next() {
+            // Final bytes of a CSI sequence
+            Some(c @ '\x40'..='\x7e') => break Some(c),
+            Some(c) => params.push(c),
+            None => break None,
+        }
+    };
+    if final_byte == Some('m') {
+        apply_sgr(&params, fore, back);
+    }
+}
+
+/// Applies the parameters of an SGR sequence to the current colors. A color of None is the terminal's default color.
+fn apply_sgr(params: &str, fore: &mut Option<Color>, back: &mut Option<Color>) {
+    let codes: Vec<u32> = params
+        .split([';', ':'])
+        .map(|x| x.parse().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => {
+                *fore = None;
+                *back = None;
+            }
+            code @ (30..=37 | 90..=97) => *fore = Some(palette_color(basic_index(code, 30))),
+            code @ (40..=47 | 100..=107) => *back = Some(palette_color(basic_index(code, 40))),
+            39 => *fore = None,
+            49 => *back = None,
+            code @ (38 | 48) => {
+                let target = if code == 38 { &mut *fore } else { &mut *back };
+                match codes.get(i + 1) {
+                    Some(5) => {
+                        if let Some(index) = codes.get(i + 2) {
+                            *target = Some(palette_color(*index as u8));
+                        }
+                        i += 2;
+                    }
+                    Some(2) => {
+                        if let [r, g, b] = codes[(i + 2).min(codes.len())..(i + 5).min(codes.len())]
+                        {
+                            *target = Some(rgb_color(r as u8, g as u8, b as u8));
+                        }
+                        i += 4;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Converts a 30-37/90-97 (or 40-47/100-107) code to its index in the 256 color palette
+fn basic_index(code: u32, base: u32) -> u8 {
+    if code >= base + 60 {
+        (code - base - 60 + 8) as u8
+    } else {
+        (code - base) as u8
+    }
+}
+
+fn palette_color(index: u8) -> Color {
+    let (r, g, b) = ansi_colours::rgb_from_ansi256(index);
+    rgb_color(r, g, b)
+}
+
+fn rgb_color(r: u8, g: u8, b: u8) -> Color {
+    Color {
+        r: r as f32 / 255.0,
+        g: g as f32 / 255.0,
+        b: b as f32 / 255.0,
+        a: 1.0,
+    }
+}
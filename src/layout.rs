@@ -0,0 +1,350 @@
+//! Placement of text output in the terminal: horizontal & vertical alignment, margins, padding with a fill color,
+//! and decorations such as a border with a title & a caption underneath.
+//!
+//! The layout is added around the rendered lines, so the art itself is never changed.
+//! Like the css box model, the art is surrounded by its padding, then the border, then the margin.
+//! Margins are left blank, while the padding is painted with the fill color.
+
+use anyhow::anyhow;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{ansi::SgrEmitter, color::RGBColorU8};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Looks up the horizontal alignment by name, None for unknown values so that the default can be picked
+pub fn get_align(name: &str) -> Option<Align> {
+    match name {
+        "left" => Some(Align::Left),
+        "center" => Some(Align::Center),
+        "right" => Some(Align::Right),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum VerticalAlign {
+    /// Starts on the current line
+    #[default]
+    Top,
+    Center,
+    Bottom,
+}
+
+/// Looks up the vertical alignment by name, None for unknown values so that the default can be picked
+pub fn get_vertical_align(name: &str) -> Option<VerticalAlign> {
+    match name {
+        "top" => Some(VerticalAlign::Top),
+        "center" => Some(VerticalAlign::Center),
+        "bottom" => Some(VerticalAlign::Bottom),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderStyle {
+    Single,
+    Double,
+    Rounded,
+    Heavy,
+}
+
+/// Looks up the border style by name, None for "none" & unknown values
+pub fn get_border_style(name: &str) -> Option<BorderStyle> {
+    match name {
+        "single" => Some(BorderStyle::Single),
+        "double" => Some(BorderStyle::Double),
+        "rounded" => Some(BorderStyle::Rounded),
+        "heavy" => Some(BorderStyle::Heavy),
+        _ => None,
+    }
+}
+
+impl BorderStyle {
+    /// Box drawing characters of the border, as top left, top right, bottom left, bottom right, horizontal & vertical
+    pub fn chars(&self) -> [char; 6] {
+        match self {
+            BorderStyle::Single => ['┌', '┐', '└', '┘', '─', '│'],
+            BorderStyle::Double => ['╔', '╗', '╚', '╝', '═', '║'],
+            BorderStyle::Rounded => ['╭', '╮', '╰', '╯', '─', '│'],
+            BorderStyle::Heavy => ['┏', '┓', '┗', '┛', '━', '┃'],
+        }
+    }
+}
+
+/// Space on each side, in columns for left & right and rows for top & bottom
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Spacing {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Spacing {
+    /// Parses the spacing like css: "all", "vertical,horizontal" or "top,right,bottom,left"
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid spacing '{}', expected 1, 2 or 4 numbers as all, vertical,horizontal or top,right,bottom,left",
+                s
+            )
+        };
+        let values = s
+            .split(',')
+            .map(|x| x.trim().parse::<usize>().ok())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let (top, right, bottom, left) = match values[..] {
+            [all] => (all, all, all, all),
+            [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+            [top, right, bottom, left] => (top, right, bottom, left),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            top,
+            right,
+            bottom,
+            left,
+        })
+    }
+
+    pub fn horizontal(&self) -> usize {
+        self.left + self.right
+    }
+
+    pub fn vertical(&self) -> usize {
+        self.top + self.bottom
+    }
+}
+
+/// Number of terminal columns the text takes up. Escape sequences take none, and wide characters such as emoji & CJK take two.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            width += c.width().unwrap_or(0);
+            continue;
+        }
+        match chars.next() {
+            // CSI, such as SGR colors, ends with a byte in @ to ~
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC, such as hyperlinks, ends with BEL or ST
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    width
+}
+
+/// Cuts the text to fit in width columns, ending it with an ellipsis when it is cut. Control characters are removed,
+/// so that text such as file names cannot inject escape sequences.
+pub fn truncate_to_width(text: &str, width: usize) -> String {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    if display_width(&text) <= width {
+        return text;
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        // One column is kept for the ellipsis
+        if used + w + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        used += w;
+    }
+    if width > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Writes the text in the colors, resetting them afterwards
+fn paint(s: &mut String, fore: Option<RGBColorU8>, back: Option<RGBColorU8>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let mut sgr = SgrEmitter::new();
+    // Writing to a string never fails
+    sgr.set(s, fore, back).unwrap();
+    s.push_str(text);
+    sgr.reset(s).unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Layout {
+    pub align: Align,
+    pub vertical_align: VerticalAlign,
+    pub margin: Spacing,
+    pub padding: Spacing,
+    /// Color of the padding, None for the terminal's background
+    pub fill: Option<RGBColorU8>,
+    pub border: Option<BorderStyle>,
+    /// Color of the border & title, None for the terminal's fore color
+    pub border_color: Option<RGBColorU8>,
+    /// Text in the top border, only shown with a border
+    pub title: Option<String>,
+    /// Line of text centered under the box
+    pub caption: Option<String>,
+    /// Size of the terminal in columns & rows, which the output is aligned in
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Layout {
+    /// Whether the layout adds nothing to the output
+    pub fn is_empty(&self) -> bool {
+        self.align == Align::Left
+            && self.vertical_align == VerticalAlign::Top
+            && self.margin == Spacing::default()
+            && self.padding == Spacing::default()
+            && self.border.is_none()
+            && self.caption.is_none()
+    }
+
+    /// Columns & rows taken up by the margins, padding, border & caption, which are not available to the art
+    pub fn reserved(&self) -> (usize, usize) {
+        let border = if self.border.is_some() { 2 } else { 0 };
+        let caption = self.caption.is_some() as usize;
+        (
+            self.margin.horizontal() + self.padding.horizontal() + border,
+            self.margin.vertical() + self.padding.vertical() + border + caption,
+        )
+    }
+
+    /// Lays out the lines of text. Lines narrower than the widest are padded on the right, so that the box stays rectangular.
+    /// Vertical alignment fills every row of the terminal except the last, which is left free for the prompt.
+    /// The title & caption are cut with an ellipsis when they are wider than the box.
+    pub fn apply(&self, text: &str) -> String {
+        let lines: Vec<&str> = text.lines().collect();
+        let widths: Vec<usize> = lines.iter().map(|line| display_width(line)).collect();
+        let content_width = widths.iter().copied().max().unwrap_or(0);
+        let inner_width = content_width + self.padding.horizontal();
+        let edge = if self.border.is_some() { 2 } else { 0 };
+        let box_width = inner_width + edge;
+        let caption = self
+            .caption
+            .as_deref()
+            .map(|caption| truncate_to_width(caption, box_width));
+        let box_height = lines.len() + self.padding.vertical() + edge + caption.is_some() as usize;
+
+        let free_columns = self
+            .columns
+            .saturating_sub(self.margin.horizontal() + box_width);
+        let indent = " ".repeat(
+            self.margin.left
+                + match self.align {
+                    Align::Left => 0,
+                    Align::Center => free_columns / 2,
+                    Align::Right => free_columns,
+                },
+        );
+        let free_rows = self
+            .rows
+            .saturating_sub(1 + self.margin.vertical() + box_height);
+        let above = match self.vertical_align {
+            VerticalAlign::Top => 0,
+            VerticalAlign::Center => free_rows / 2,
+            VerticalAlign::Bottom => free_rows,
+        };
+        // Centered & bottom aligned outputs fill the rest of the screen too, so that wherever the cursor starts,
+        // the screen scrolls until the output takes up every row
+        let below = match self.vertical_align {
+            VerticalAlign::Top => 0,
+            _ => free_rows - above,
+        };
+        let top = self.margin.top + above;
+
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] =
+            self.border.map(|style| style.chars()).unwrap_or([' '; 6]);
+        let vertical = vertical.to_string();
+        let mut s =
+            String::with_capacity(text.len() + (box_height + top) * (indent.len() + box_width));
+        // Writes a row of the box around its content, which is inner_width - width columns short of the border
+        let row = |s: &mut String, content: &str, width: usize, left: usize| {
+            s.push_str(&indent);
+            if self.border.is_some() {
+                paint(s, self.border_color, None, &vertical);
+            }
+            paint(s, None, self.fill, &" ".repeat(left));
+            s.push_str(content);
+            paint(s, None, self.fill, &" ".repeat(inner_width - width - left));
+            if self.border.is_some() {
+                paint(s, self.border_color, None, &vertical);
+            }
+            s.push('\n');
+        };
+
+        s.push_str(&"\n".repeat(top));
+        if self.border.is_some() {
+            // The title needs a border character & a space on each side
+            let title = match &self.title {
+                Some(title) if inner_width >= 5 => {
+                    let title = truncate_to_width(title, inner_width - 4);
+                    format!("{} {} ", horizontal, title)
+                }
+                _ => String::new(),
+            };
+            let rest = inner_width - display_width(&title);
+            let line = format!(
+                "{}{}{}{}",
+                top_left,
+                title,
+                horizontal.to_string().repeat(rest),
+                top_right
+            );
+            s.push_str(&indent);
+            paint(&mut s, self.border_color, None, &line);
+            s.push('\n');
+        }
+        for _ in 0..self.padding.top {
+            row(&mut s, "", 0, 0);
+        }
+        for (line, width) in lines.iter().zip(&widths) {
+            row(&mut s, line, *width, self.padding.left);
+        }
+        for _ in 0..self.padding.bottom {
+            row(&mut s, "", 0, 0);
+        }
+        if self.border.is_some() {
+            let line = format!(
+                "{}{}{}",
+                bottom_left,
+                horizontal.to_string().repeat(inner_width),
+                bottom_right
+            );
+            s.push_str(&indent);
+            paint(&mut s, self.border_color, None, &line);
+            s.push('\n');
+        }
+        if let Some(caption) = caption {
+            s.push_str(&indent);
+            s.push_str(&" ".repeat((box_width - display_width(&caption)) / 2));
+            s.push_str(&caption);
+            s.push('\n');
+        }
+        s.push_str(&"\n".repeat(self.margin.bottom + below));
+        s
+    }
+}
@@ -7,9 +7,21 @@ pub enum NearestOption {
 }
 pub mod charsets;
 pub mod color;
+pub mod config;
+pub mod fetch;
+pub mod fit;
 pub mod ansi;
+pub mod batch;
+pub mod cache;
 pub mod cell;
 pub mod outputs;
+pub mod grid_file;
+pub mod import;
+pub mod layout;
+pub mod limits;
+pub mod passthrough;
+pub mod terminal;
+pub mod transform;
 
 pub mod utils{
     use std::process::exit;
@@ -26,6 +38,16 @@ pub mod utils{
         }
     }
 
+    /// Decodes an image from its bytes within the default limits, guessing the format from the magic bytes at its start
+    pub fn decode_image(bytes: &[u8]) -> anyhow::Result<image::DynamicImage> {
+        if bytes.is_empty() {
+            anyhow::bail!("The image data is empty");
+        }
+        let format = image::guess_format(bytes)
+            .map_err(|_| anyhow::anyhow!("Could not recognise the image format from its data"))?;
+        crate::limits::DecodeLimits::default().decode(bytes, format)
+    }
+
     pub fn get_scaling(name: &str) -> image::imageops::FilterType{
         match name {
             "nearest" => image::imageops::FilterType::Nearest,
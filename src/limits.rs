@@ -0,0 +1,166 @@
+//! Safety limits on the size of decoded images, so that huge or malicious images fail early instead of exhausting memory.
+
+use std::{fmt, io::Cursor};
+
+use image::{
+    metadata::Orientation, DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader,
+};
+
+/// Default maximum width & height of a decoded image in pixels
+pub const DEFAULT_MAX_DIMENSION: u32 = 16384;
+/// Default maximum memory used for decoding & converting an image, 512 MiB
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+/// Size of each pixel once converted to f32 RGBA for computing the cells
+pub const F32_PIXEL_BYTES: u64 = 16;
+
+/// An image was rejected for being over one of the limits
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitError {
+    Dimensions {
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+    },
+    /// Decoding or converting the image would use more memory than allowed
+    Memory { needed: Option<u64>, max: u64 },
+    /// The downloaded data is larger than allowed
+    Download { max: u64 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Dimensions {
+                width,
+                height,
+                max_width,
+                max_height,
+            } => write!(
+                f,
+                "Image is {}x{} pixels, over the limit of {}x{}, which can be raised with --max-image-size",
+                width, height, max_width, max_height
+            ),
+            LimitError::Memory {
+                needed: Some(needed),
+                max,
+            } => write!(
+                f,
+                "Image needs {} bytes of memory, over the limit of {} bytes, which can be raised with --max-decoded-size",
+                needed, max
+            ),
+            LimitError::Memory { needed: None, max } => write!(
+                f,
+                "Decoding the image needs more than the limit of {} bytes of memory, which can be raised with --max-decoded-size",
+                max
+            ),
+            LimitError::Download { max } => write!(
+                f,
+                "Data is larger than the download limit of {} bytes, which can be raised with --max-download",
+                max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Maximum memory in bytes for the decoded image, and for its f32 conversion
+    pub max_bytes: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_width: DEFAULT_MAX_DIMENSION,
+            max_height: DEFAULT_MAX_DIMENSION,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub fn image_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(self.max_width);
+        limits.max_image_height = Some(self.max_height);
+        limits.max_alloc = Some(self.max_bytes);
+        limits
+    }
+
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), LimitError> {
+        if width > self.max_width || height > self.max_height {
+            return Err(LimitError::Dimensions {
+                width,
+                height,
+                max_width: self.max_width,
+                max_height: self.max_height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that an image of this size can be converted to f32 RGBA, which uses 16 bytes per pixel
+    pub fn check_f32(&self, width: u32, height: u32) -> Result<(), LimitError> {
+        let needed = width as u64 * height as u64 * F32_PIXEL_BYTES;
+        if needed > self.max_bytes {
+            return Err(LimitError::Memory {
+                needed: Some(needed),
+                max: self.max_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes the image within the limits, and rotates it upright with its EXIF orientation. The dimensions are read from
+    /// the header first, so that oversized images are rejected before anything is allocated.
+    pub fn decode(&self, bytes: &[u8], format: ImageFormat) -> anyhow::Result<DynamicImage> {
+        let (width, height) =
+            ImageReader::with_format(Cursor::new(bytes), format).into_dimensions()?;
+        self.check_dimensions(width, height)?;
+
+        let limit_error = |e: ImageError| -> anyhow::Error {
+            match e {
+                ImageError::Limits(_) => LimitError::Memory {
+                    needed: None,
+                    max: self.max_bytes,
+                }
+                .into(),
+                e => e.into(),
+            }
+        };
+        let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+        reader.limits(self.image_limits());
+        let mut decoder = reader.into_decoder().map_err(limit_error)?;
+        let needed = decoder.total_bytes();
+        if needed > self.max_bytes {
+            return Err(LimitError::Memory {
+                needed: Some(needed),
+                max: self.max_bytes,
+            }
+            .into());
+        }
+        // Images without EXIF data, or with invalid EXIF data, are kept as they are
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let mut img = DynamicImage::from_decoder(decoder).map_err(limit_error)?;
+        img.apply_orientation(orientation);
+        Ok(img)
+    }
+}
+
+/// Parses a size in pixels given as `WIDTHxHEIGHT`, or a single number used for both
+pub fn parse_dimensions(size: &str) -> anyhow::Result<(u32, u32)> {
+    let invalid = || anyhow::anyhow!("Invalid image size '{}', expected eg. 8000x6000", size);
+    let (width, height) = match size.trim().split_once(['x', 'X']) {
+        Some((w, h)) => (w.trim(), h.trim()),
+        None => (size.trim(), size.trim()),
+    };
+    Ok((
+        width.parse().map_err(|_| invalid())?,
+        height.parse().map_err(|_| invalid())?,
+    ))
+}
@@ -1,37 +1,89 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs::{self, File},
-    io::Write,
+    io::{self, BufWriter, Cursor, IsTerminal, Read, Write},
+    path::PathBuf,
     process::ExitCode,
+    time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use argh::FromArgs;
 use clir_rs::{
-    cell::{self, CellGrid, CELL_H, CELL_W},
+    batch,
+    cache::{self, Cache},
+    cell::{self, CellGrid, ComputedCellGrid, CELL_H, CELL_W},
     charsets,
     color::Color,
-    outputs::AsciiImageRenderer,
+    config::{self, ConfigFile, Settings},
+    fetch::{self, FetchSettings},
+    fit::{self, Fit, FitMode},
+    grid_file,
+    import::{self, ImportWarnings},
+    layout::{self, Layout, Spacing},
+    limits::{self, DecodeLimits},
+    outputs::{
+        self,
+        ans::{self, AnsImageRenderer, SauceMetadata},
+        bitmap_font::{GLYPH_H, GLYPH_W},
+        codegen::{CodeGenerator, CodegenSettings},
+        emoji::EmojiImageRenderer,
+        irc::IrcImageRenderer,
+        kitty::{self, KittyImageRenderer},
+        png::PngImageRenderer,
+        sixel::SixelImageRenderer,
+        svg::SvgImageRenderer,
+        svg::SvgSettings,
+        AsciiImageRenderer, CaptureWriter, MultiWriter, OutputFormat,
+    },
+    passthrough::{self, Passthrough, PassthroughWriter},
+    terminal,
+    transform::{self, Crop, Transform, Trim},
     utils,
 };
-use image::{DynamicImage, ImageReader};
-use is_url::is_url;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, RgbaImage};
+use rayon::prelude::*;
 
 #[derive(FromArgs, Debug)]
 /// Renders an image to the console as unicode art
 struct CliArgs {
-    /// filepath or url to the source image. Note that not all urls might work, it is recommended to download the image separately as an png first.
+    /// filepath or url to the source image, or `-` to read it from stdin. Urls can be http(s) links, `data:` uris or `file://` urls.
+    /// When --load-grid or --import-art is set, this is the path to a saved grid or unicode art file instead, which can also be read from stdin with `-`.
+    /// Multiple sources, glob patterns & directories can be given to render a batch of files into --output-dir.
     #[argh(positional)]
-    source: String,
+    source: Vec<String>,
 
     /// saves output to this path.
     #[argh(option)]
     output: Option<String>,
 
+    /// renders every source into its own file in this directory, in parallel. Needed when there are multiple sources.
+    #[argh(option)]
+    output_dir: Option<String>,
+
+    /// file name of each output in --output-dir, where {stem} is the source's file name without extension, {ext} the extension of the format
+    /// and {index} the position of the source in the batch [default: "{stem}.{ext}"]
+    #[argh(option)]
+    output_template: Option<String>,
+
+    /// also renders the files in subdirectories of directory sources.
+    #[argh(switch)]
+    recursive: bool,
+
+    /// disables --recursive from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_recursive: bool,
+
     /// disables automatic resizing of output size to fit the terminal if available. Using --width or --height will override the detected values.
     ///
     /// When not available or disabled, autosize sets width to 100, height is derived from aspect ratio . If --no-keep-aspect is set, height will be set to 25
     #[argh(switch)]
     no_autosize: bool,
 
+    /// enables autosize, overriding --no-autosize from the config file, a preset or the environment.
+    #[argh(switch)]
+    autosize: bool,
+
     /// specify width of the output in number of chars.
     #[argh(option, short = 'w')]
     width: Option<usize>,
@@ -44,14 +96,117 @@ struct CliArgs {
     #[argh(switch)]
     no_keep_aspect: bool,
 
+    /// keeps the aspect ratio, overriding --no-keep-aspect from the config file, a preset or the environment.
+    #[argh(switch)]
+    keep_aspect: bool,
+
+    /// width / height of a terminal cell, used to keep the aspect ratio, as a number (eg. "0.45") or a ratio (eg. "9:20").
+    /// "auto" reads it from the terminal's size in pixels for the ansi, emoji, sixel & kitty formats, falling back to 0.5 when the
+    /// terminal does not report it, while png & svg use the size of their characters [default: "auto"]
+    #[argh(option)]
+    cell_aspect: Option<String>,
+
+    /// crops the source image to x,y,width,height before sizing it, each in pixels or as a percentage of the image, eg. "0,0,50%,50%".
+    /// The image is first turned upright with its EXIF orientation.
+    #[argh(option)]
+    crop: Option<String>,
+
+    /// rotates the source image clockwise by 90, 180 or 270 degrees, after cropping.
+    #[argh(option)]
+    rotate: Option<u32>,
+
+    /// flips the source image after cropping & rotating. Valid options are ["h", "v"] for horizontal & vertical.
+    #[argh(option)]
+    flip: Option<String>,
+
+    /// crops the transparent or solid color margins around the image before sizing it, after --crop, --rotate & --flip.
+    /// The margin color is taken from the top left corner.
+    #[argh(switch)]
+    trim: bool,
+
+    /// disables --trim from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_trim: bool,
+
+    /// how different from the margin a pixel can be while still being trimmed, from 0 to 1. Pixels with alpha up to the tolerance are also trimmed [default: 0.1]
+    #[argh(option)]
+    trim_tolerance: Option<f32>,
+
+    /// pixels of margin kept around the content by --trim [default: 0]
+    #[argh(option)]
+    trim_padding: Option<u32>,
+
+    /// how the image is fitted into the output size. Valid options are ["keep", "contain", "cover", "fill"]. Uses default for unknown values [default: "keep"]
+    /// keep shrinks the width or height of the output to the image's aspect ratio. contain, cover & fill always output exactly the width x height:
+    /// contain pads the image with --background, cover crops the overflow around --gravity and fill stretches the image. --no-keep-aspect is the same as fill.
+    #[argh(option)]
+    fit: Option<String>,
+
+    /// part of the image kept when cropping with --fit cover. Valid options are ["center", "top", "bottom", "left", "right"], corners such as "top-left",
+    /// or a focal point "x,y" as fractions of the image's width & height, eg. "0.5,0.2". Uses default for unknown values [default: "center"]
+    #[argh(option)]
+    gravity: Option<String>,
+
+    /// aligns ansi & emoji outputs in the terminal's width. Valid options are ["left", "center", "right"]. Uses default for unknown values [default: "left"]
+    #[argh(option)]
+    align: Option<String>,
+
+    /// aligns ansi & emoji outputs in the terminal's height, for fullscreen display. The last row is left for the prompt.
+    /// Valid options are ["top", "center", "bottom"]. Uses default for unknown values [default: "top"]
+    #[argh(option)]
+    valign: Option<String>,
+
+    /// blank space around ansi & emoji outputs, in columns & rows, as "all", "vertical,horizontal" or "top,right,bottom,left" [default: 0]
+    #[argh(option)]
+    margin: Option<String>,
+
+    /// space between ansi & emoji outputs and their margin, filled with --padding-color, in the same format as --margin [default: 0]
+    #[argh(option)]
+    padding: Option<String>,
+
+    /// color of the --padding as a hex color, eg. "#1e1e2e". Uses the terminal's background for unknown values [default: "none"]
+    #[argh(option)]
+    padding_color: Option<String>,
+
+    /// draws a border around ansi & emoji outputs, outside of the padding. Valid options are ["none", "single", "double", "rounded", "heavy"].
+    /// Uses default for unknown values [default: "none"]
+    #[argh(option)]
+    border: Option<String>,
+
+    /// color of the --border & its title as a hex color, eg. "#89b4fa", or "auto" for the most common color of the image.
+    /// Uses the terminal's fore color for unknown values [default: "none"]
+    #[argh(option)]
+    border_color: Option<String>,
+
+    /// text shown in the top --border, cut to fit the border. {name}, {stem}, {width} & {height} are replaced by the source's file name,
+    /// its file name without extension, and the image's size in pixels (or the grid's size in cells with --load-grid & --import-art)
+    #[argh(option)]
+    title: Option<String>,
+
+    /// line of text centered under ansi & emoji outputs, cut to fit their width, eg. "{name} {width}x{height}". Same placeholders as --title
+    #[argh(option)]
+    caption: Option<String>,
+
+    /// color of the padding added by --fit contain, as a hex color (eg. "#1e1e2e") or "transparent" [default: "transparent"]
+    #[argh(option)]
+    background: Option<String>,
+
     /// disables colors, in rendered output. Result will be black & white
     #[argh(switch)]
     no_color: bool,
 
+    /// enables colors, overriding --no-color from the config file, a preset or the environment.
+    #[argh(switch)]
+    color: bool,
+
     /// enables plain text mode, useful for rendering unicode art.
     #[argh(switch)]
     plain_text: bool,
 
+    /// disables plain text mode, overriding --plain-text from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_plain_text: bool,
+
     /// enable debug outputs, which will be stored in `./clir_rs_debug/`
     #[argh(switch)]
     debug: bool,
@@ -60,6 +215,10 @@ struct CliArgs {
     #[argh(switch)]
     use_original_image_size: bool,
 
+    /// uses the size options, overriding --use-original-image-size from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_use_original_image_size: bool,
+
     /// when set, doesn't print out the resulting unicode art. Still prints debug & other information
     #[argh(switch)]
     no_print: bool,
@@ -80,6 +239,161 @@ struct CliArgs {
     /// inverts the fore and background cell mask. Colors are also inverted (such that there is no effect on color) respectively.
     #[argh(switch)]
     invert_cell: bool,
+
+    /// disables --invert-cell from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_invert_cell: bool,
+
+    /// sets the format of the rendered output. Valid options are ["ansi", "svg", "png", "ans", "irc", "emoji", "rust", "c", "sh", "sixel", "kitty"]. Uses default for unknown values [default: "ansi"]
+    /// The png format draws the output as it would look in a terminal. The ans format is CP437 encoded BBS style art with 16 colors and a SAUCE record.
+    /// Both are only saved to --output, never printed. The irc format uses mIRC color codes instead of ANSI escape sequences.
+    /// The emoji format draws the image with colored emoji squares, for chats that do not render ANSI. Each cell becomes two emoji stacked vertically, and the size options count emoji as two columns.
+    /// The rust, c & sh formats generate source code embedding the ansi output, as a Rust const, a C header or a POSIX printf script.
+    /// The sixel & kitty formats show the image at pixel resolution with the sixel or kitty graphics protocol, covering the same number of cells as the unicode art.
+    #[argh(option)]
+    format: Option<String>,
+
+    /// font family used for the text in svg output [default: "monospace"]
+    #[argh(option)]
+    svg_font_family: Option<String>,
+
+    /// font size used for the text in svg output [default: 14]
+    #[argh(option)]
+    svg_font_size: Option<f32>,
+
+    /// width of each character cell in svg output [default: 8.4]
+    #[argh(option)]
+    svg_cell_width: Option<f32>,
+
+    /// height of each row of characters in svg output [default: 17]
+    #[argh(option)]
+    svg_line_height: Option<f32>,
+
+    /// scale factor of the png output. At scale 1, each character is 8x16 pixels [default: 1]
+    #[argh(option)]
+    png_scale: Option<u32>,
+
+    /// title stored in the SAUCE record of ans output
+    #[argh(option)]
+    sauce_title: Option<String>,
+
+    /// author stored in the SAUCE record of ans output
+    #[argh(option)]
+    sauce_author: Option<String>,
+
+    /// group stored in the SAUCE record of ans output
+    #[argh(option)]
+    sauce_group: Option<String>,
+
+    /// font stored in the SAUCE record of ans output [default: "IBM VGA"]
+    #[argh(option)]
+    sauce_font: Option<String>,
+
+    /// maximum number of bytes in each line of irc output. Longer lines are truncated. IRC servers usually limit messages to 512 bytes, including the command & channel name.
+    #[argh(option)]
+    irc_line_limit: Option<usize>,
+
+    /// name of the constant in rust & c output [default: "ART"]
+    #[argh(option)]
+    codegen_name: Option<String>,
+
+    /// generates an array with one string per line in rust & c output, instead of a single string.
+    #[argh(switch)]
+    codegen_lines: bool,
+
+    /// generates a single string, overriding --codegen-lines from the config file, a preset or the environment.
+    #[argh(switch)]
+    no_codegen_lines: bool,
+
+    /// how the image is transmitted in kitty output. Valid options are ["png", "rgba"]. Uses default for unknown values [default: "png"]
+    #[argh(option)]
+    kitty_format: Option<String>,
+
+    /// wraps escape sequences printed by the ansi, sixel & kitty formats so that they pass through tmux or screen. Valid options are ["auto", "tmux", "screen", "none"] [default: "auto"]
+    /// auto detects tmux & screen from the environment, but only wraps sixel & kitty output, as wrapped colors bypass tmux's own screen and need `allow-passthrough` to be enabled.
+    #[argh(option)]
+    passthrough: Option<String>,
+
+    /// saves the computed grid to this path, so that it can be rendered again later with --load-grid. Saved as json when the path ends with `.json`, otherwise in a compact binary form.
+    #[argh(option)]
+    save_grid: Option<String>,
+
+    /// renders a grid saved with --save-grid instead of an image. Size, scaling & cell options have no effect as the grid is already computed.
+    #[argh(switch)]
+    load_grid: bool,
+
+    /// imports existing unicode art (eg. .txt or .ans files made of braille or block characters, optionally colored with SGR sequences) instead of an image.
+    /// Size, scaling & cell options have no effect.
+    #[argh(switch)]
+    import_art: bool,
+
+    /// saves the cells as an image, where each character becomes CELL_W x CELL_H pixels of its fore & back colors.
+    #[argh(option)]
+    save_cells: Option<String>,
+
+    /// maximum time in seconds to download an image from a url, including reading the whole response [default: 30]
+    #[argh(option)]
+    timeout: Option<f32>,
+
+    /// maximum time in seconds to connect to the server of a url [default: 10]
+    #[argh(option)]
+    connect_timeout: Option<f32>,
+
+    /// maximum time in seconds to wait for the response of a url or for more of its data, so that a stalled download fails
+    /// before --timeout [default: 10]
+    #[argh(option)]
+    read_timeout: Option<f32>,
+
+    /// maximum size of an image downloaded from a url, in bytes or with a K, M or G suffix [default: "50M"]
+    #[argh(option)]
+    max_download: Option<String>,
+
+    /// value of the User-Agent header sent when downloading from a url [default: "clir_rs/<version>"]
+    #[argh(option)]
+    user_agent: Option<String>,
+
+    /// extra header sent when downloading from a url, as "Name: value". Can be given multiple times.
+    #[argh(option)]
+    header: Vec<String>,
+
+    /// maximum width & height of a source image in pixels, as WIDTHxHEIGHT or a single number for both. Larger images are rejected before decoding [default: "16384x16384"]
+    #[argh(option)]
+    max_image_size: Option<String>,
+
+    /// maximum memory used to decode a source image, and to convert the resized image for computing the cells (16 bytes per pixel).
+    /// In bytes or with a K, M or G suffix [default: "512M"]
+    #[argh(option)]
+    max_decoded_size: Option<String>,
+
+    /// path to the config file, which sets default options & named presets in toml. Keys are the names of the options, eg. `transparency-t = 0.5`.
+    /// [default: "<config dir>/clir_rs/config.toml"]
+    #[argh(option)]
+    config: Option<String>,
+
+    /// applies the options of a preset from the config file, defined as a [presets.<name>] table. Can also be set with CLIR_PRESET.
+    /// Options are taken from the command line first, then CLIR_* environment variables (eg. CLIR_WIDTH), the preset and the config file.
+    #[argh(option)]
+    preset: Option<String>,
+
+    /// prints the effective options as toml, from the built-in defaults, config file, preset, environment & command line, without rendering.
+    #[argh(switch)]
+    print_config: bool,
+
+    /// disables the cache of downloaded images & rendered outputs, which is kept in "<cache dir>/clir_rs".
+    #[argh(switch)]
+    no_cache: bool,
+
+    /// enables the cache, overriding --no-cache from the config file, a preset or the environment.
+    #[argh(switch)]
+    cache: bool,
+
+    /// removes everything from the cache before rendering. Can be used without a source.
+    #[argh(switch)]
+    clear_cache: bool,
+
+    /// maximum size of the cache, in bytes or with a K, M or G suffix. The least recently used entries are removed first [default: "100M"]
+    #[argh(option)]
+    cache_size: Option<String>,
 }
 
 const DEFAULT_WIDTH: usize = 100;
@@ -94,23 +408,24 @@ enum RenderMode {
 struct RenderSettings {
     im_height: u32,
     im_width: u32,
+    fit: FitMode,
+    /// Width / height of a terminal cell
+    cell_aspect: f32,
     render_mode: RenderMode,
-    output: Option<String>,
     src: DynamicImage,
 }
 
 impl RenderSettings {
-    // Reduces width or height to match the aspect ratio
+    // Reduces width or height to match the aspect ratio, where each cell is cell_aspect times as wide as it is tall
     pub fn keep_aspect(
         width: usize,
         height: usize,
         aspect: f32,
+        cell_aspect: f32,
         use_width: Option<bool>,
     ) -> (usize, usize) {
-        let width_ = CELL_W * width;
-        let height_ = CELL_H * height;
-        let new_width = (height_ as f32 * aspect / CELL_W as f32).floor();
-        let new_height = (width_ as f32 / aspect / CELL_H as f32).floor();
+        let new_width = (height as f32 * aspect / cell_aspect).floor();
+        let new_height = (width as f32 * cell_aspect / aspect).floor();
         if use_width.unwrap_or(new_height < (height as f32)) {
             (width, new_height as usize)
         } else {
@@ -125,21 +440,73 @@ impl RenderSettings {
         }
     }
 
-    pub fn from_args(args: &CliArgs, img: DynamicImage) -> Self {
+    /// Cell aspect from --cell-aspect. With auto, it is detected from the terminal for outputs shown in it,
+    /// while png & svg use the geometry of their characters.
+    pub fn cell_aspect(args: &CliArgs, format: OutputFormat) -> anyhow::Result<f32> {
+        match args.cell_aspect.as_deref().unwrap_or("auto") {
+            "auto" => Ok(match format {
+                _ if format.is_shown_in_terminal() => {
+                    terminal::detect_cell_aspect().unwrap_or(terminal::DEFAULT_CELL_ASPECT)
+                }
+                OutputFormat::Png => GLYPH_W as f32 / GLYPH_H as f32,
+                OutputFormat::Svg => {
+                    let settings = svg_settings(args);
+                    settings.cell_width / settings.line_height
+                }
+                _ => terminal::DEFAULT_CELL_ASPECT,
+            }),
+            value => terminal::parse_cell_aspect(value).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid cell aspect '{}', expected a number such as 0.45, a ratio such as 9:20, or auto",
+                    value
+                )
+            }),
+        }
+    }
+
+    /// cell_span is the number of columns & rows each cell takes up in the output, see OutputFormat::cell_span
+    pub fn from_args(
+        args: &CliArgs,
+        img: DynamicImage,
+        cell_span: usize,
+        cell_aspect: f32,
+        reserved: (usize, usize),
+    ) -> Self {
         let aspect = img.width() as f32 / img.height() as f32;
+        let fit = fit::get_fit_mode(args.fit.as_deref().unwrap_or("")).unwrap_or(
+            if args.no_keep_aspect {
+                FitMode::Fill
+            } else {
+                FitMode::Keep
+            },
+        );
 
         let output_size = if args.use_original_image_size {
-            (img.width() as usize, img.height() as usize)
+            // Divided like the other sizes, so that wider cells such as emoji take up the same area
+            (
+                (img.width() as usize / cell_span).max(1),
+                (img.height() as usize / cell_span).max(1),
+            )
         } else {
             let (dw, dh) = if args.no_autosize {
                 (DEFAULT_WIDTH, DEFAULT_HEIGHT)
             } else {
-                Self::autodetected_size()
+                // The columns & rows reserved for the layout are left free, so that the output still fits the terminal
+                let (columns, rows) = Self::autodetected_size();
+                (
+                    columns.saturating_sub(reserved.0).max(1),
+                    rows.saturating_sub(reserved.1).max(1),
+                )
             };
 
-            let unwrapped_size = (args.width.unwrap_or(dw), args.height.unwrap_or(dh));
+            let unwrapped_size = (
+                (args.width.unwrap_or(dw) / cell_span).max(1),
+                (args.height.unwrap_or(dh) / cell_span).max(1),
+            );
 
-            let (fw, fh) = if args.no_keep_aspect || (args.width.is_some() && args.height.is_some())
+            let (fw, fh) = if args.no_keep_aspect
+                || fit != FitMode::Keep
+                || (args.width.is_some() && args.height.is_some())
             {
                 unwrapped_size
             } else {
@@ -150,16 +517,20 @@ impl RenderSettings {
                     unwrapped_size.0,
                     unwrapped_size.1,
                     aspect,
+                    cell_aspect,
                     Some(args.width.is_some()),
                 )
             };
 
-            (fw * CELL_W, fh * CELL_H)
+            // Sizes smaller than a cell, eg. -w 1 with emoji, still get a single cell
+            (fw.max(1) * CELL_W, fh.max(1) * CELL_H)
         };
 
         Self {
             im_height: output_size.1 as u32,
             im_width: output_size.0 as u32,
+            fit,
+            cell_aspect,
             render_mode: if args.plain_text {
                 RenderMode::PlainText
             } else if args.no_color {
@@ -167,50 +538,239 @@ impl RenderSettings {
             } else {
                 RenderMode::Color
             },
-            output: args.output.clone(),
             src: img,
         }
     }
 }
 
-fn read_image(path: &str, debug: bool) -> anyhow::Result<DynamicImage> {
-    anyhow::Ok(if is_url(path) {
+/// Reads all of stdin, for sources given as `-`
+fn read_stdin() -> anyhow::Result<Vec<u8>> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        anyhow::bail!(
+            "Source is '-' but stdin is a terminal. Pipe the data in instead, eg. `curl <url> | clir_rs -`"
+        );
+    }
+    let mut bytes = Vec::new();
+    stdin.read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        anyhow::bail!("Source is '-' but nothing was read from stdin");
+    }
+    Ok(bytes)
+}
+
+/// Opens the cache unless --no-cache is set, after clearing it with --clear-cache
+fn open_cache(args: &CliArgs) -> anyhow::Result<Option<Cache>> {
+    let max_size = match &args.cache_size {
+        Some(size) => fetch::parse_size(size).context("Invalid --cache-size")?,
+        None => cache::DEFAULT_MAX_SIZE,
+    };
+    let Some(dir) = Cache::default_dir() else {
+        return Ok(None);
+    };
+    let cache = Cache::new(dir, max_size);
+    if args.clear_cache {
+        cache
+            .clear()
+            .with_context(|| format!("Failed to clear the cache at {:?}", cache.dir()))?;
+        if args.debug {
+            println!("Cleared the cache at {:?}", cache.dir());
+        }
+    }
+    Ok((!args.no_cache).then_some(cache))
+}
+
+/// Removes the least recently used entries when the cache is over its size limit
+fn evict_cache(cache: Option<&Cache>, debug: bool) {
+    match cache.map(|cache| cache.evict()) {
+        Some(Ok(removed)) if debug && removed > 0 => {
+            println!("Removed {} bytes from the cache", removed)
+        }
+        Some(Err(e)) => eprintln!("Warning: failed to clean up the cache due to {:?}", e),
+        _ => {}
+    }
+}
+
+/// Settings for fetching sources given as urls
+fn fetch_settings(args: &CliArgs, cache: Option<&Cache>) -> anyhow::Result<FetchSettings> {
+    let mut settings = FetchSettings {
+        cache: cache.cloned(),
+        ..Default::default()
+    };
+    if let Some(t) = args.timeout {
+        settings.timeout = Duration::try_from_secs_f32(t).context("Invalid --timeout")?;
+    }
+    if let Some(t) = args.connect_timeout {
+        settings.connect_timeout =
+            Duration::try_from_secs_f32(t).context("Invalid --connect-timeout")?;
+    }
+    if let Some(t) = args.read_timeout {
+        settings.read_timeout = Duration::try_from_secs_f32(t).context("Invalid --read-timeout")?;
+    }
+    if let Some(size) = &args.max_download {
+        settings.max_size = fetch::parse_size(size)?;
+    }
+    if let Some(user_agent) = &args.user_agent {
+        settings.user_agent = user_agent.clone();
+    }
+    settings.headers = args
+        .header
+        .iter()
+        .map(|x| fetch::parse_header(x))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(settings)
+}
+
+/// Reads the bytes of a source: stdin when the path is `-`, a url for images, or a file
+fn read_source(args: &CliArgs, path: &str, cache: Option<&Cache>) -> anyhow::Result<Vec<u8>> {
+    let debug = args.debug;
+    if path == "-" {
         if debug {
-            println!("Requesting image from url '{:?}'", path);
+            println!("Reading source from stdin");
         }
-        let res = reqwest::blocking::get(path)?;
+        read_stdin()
+    } else if fetch::is_url(path) && !args.load_grid && !args.import_art {
         if debug {
-            println!("Response '{:#?}'", res);
+            println!("Fetching image from url '{:?}'", path);
         }
-        let bytes = res.bytes()?;
-        image::load_from_memory(&bytes)?
+        let bytes = fetch::fetch(path, &fetch_settings(args, cache)?)?;
+        if debug {
+            println!("Fetched {} bytes", bytes.len());
+        }
+        Ok(bytes)
     } else {
         if debug {
-            println!("Expanding source image '{:?}'", &path);
+            println!("Expanding source '{:?}'", &path);
         }
         let expanded = utils::expand_path(path);
-        println!("Reading image from '{:?}'", expanded);
-        ImageReader::open(expanded)?.decode()?
-    })
+        if debug && !args.load_grid && !args.import_art {
+            println!("Reading image from '{:?}'", expanded);
+        }
+        Ok(fs::read(expanded)?)
+    }
 }
 
-fn main() -> ExitCode {
-    let before_cmd = Instant::now();
-    let args: CliArgs = argh::from_env();
-
-    if args.debug {
-        println!("Running with arguments: {:#?}", args);
+/// Limits on the size of source images
+fn decode_limits(args: &CliArgs) -> anyhow::Result<DecodeLimits> {
+    let mut limits = DecodeLimits::default();
+    if let Some(size) = &args.max_image_size {
+        (limits.max_width, limits.max_height) = limits::parse_dimensions(size)?;
     }
+    if let Some(size) = &args.max_decoded_size {
+        limits.max_bytes = fetch::parse_size(size).context("Invalid --max-decoded-size")?;
+    }
+    Ok(limits)
+}
 
-    let img = match read_image(&args.source, args.debug) {
-        Ok(x) => x,
-        Err(e) => {
-            eprintln!("Fatal error while trying to read image: {:#?}", e);
-            return ExitCode::FAILURE;
+/// Decodes the image read from the source. Files without a recognisable signature are decoded by their extension.
+fn decode_source(path: &str, bytes: &[u8], limits: &DecodeLimits) -> anyhow::Result<DynamicImage> {
+    let format = image::guess_format(bytes).or_else(|e| {
+        if path == "-" || fetch::is_url(path) {
+            Err(e)
+        } else {
+            ImageFormat::from_path(path)
         }
+    });
+    match format {
+        Ok(format) => limits.decode(bytes, format),
+        Err(_) => utils::decode_image(bytes),
+    }
+}
+
+/// Key of the cached output, from the source bytes & every setting affecting the render
+fn output_key(
+    args: &CliArgs,
+    settings: &Settings,
+    format: OutputFormat,
+    source: &str,
+    bytes: &[u8],
+) -> String {
+    let render_settings = Settings {
+        output_template: None,
+        recursive: None,
+        timeout: None,
+        connect_timeout: None,
+        read_timeout: None,
+        max_download: None,
+        user_agent: None,
+        no_cache: None,
+        cache_size: None,
+        max_image_size: None,
+        max_decoded_size: None,
+        ..settings.clone()
+    };
+    // The size of autosized outputs & the detected cell aspect depend on the terminal
+    let (width, height) = RenderSettings::autodetected_size();
+    // Saved grids & unicode art are not sized, so the terminal is not queried for them
+    let cell_aspect = if args.load_grid || args.import_art {
+        None
+    } else {
+        RenderSettings::cell_aspect(args, format).ok()
+    };
+    // Graphics are sized by the pixels of the terminal's cells
+    let cell_size = if format.is_graphics() {
+        terminal::detect_cell_size()
+    } else {
+        None
     };
+    let source_kind = format!(
+        "{}x{} cell_aspect={:?} cell_size={:?} load_grid={} import_art={}",
+        width, height, cell_aspect, cell_size, args.load_grid, args.import_art
+    );
+    // The title & caption can contain the source's name
+    let name = if args.title.is_some() || args.caption.is_some() {
+        source
+    } else {
+        ""
+    };
+    cache::hash_key(&[
+        env!("CARGO_PKG_VERSION").as_bytes(),
+        bytes,
+        render_settings.to_toml().as_bytes(),
+        source_kind.as_bytes(),
+        name.as_bytes(),
+    ])
+}
 
-    let config = RenderSettings::from_args(&args, img);
+/// Source image & intermediate results of computing the grid from it, kept for debug outputs
+struct ImagePipeline {
+    config: RenderSettings,
+    img: DynamicImage,
+    cells: CellGrid,
+    cell_time: Duration,
+    compute_time: Duration,
+}
+
+fn compute_from_image(
+    args: &CliArgs,
+    source: &str,
+    bytes: &[u8],
+    format: OutputFormat,
+) -> anyhow::Result<(ComputedCellGrid, ImagePipeline)> {
+    let limits = decode_limits(args)?;
+    let img = decode_source(source, bytes, &limits)?;
+    let transform = Transform {
+        trim: args.trim.then(|| Trim {
+            tolerance: args.trim_tolerance.unwrap_or(Trim::DEFAULT_TOLERANCE),
+            padding: args.trim_padding.unwrap_or(0),
+        }),
+        ..Transform::new(
+            args.crop.as_deref().map(Crop::parse).transpose()?,
+            args.rotate.unwrap_or(0),
+            transform::get_flip(args.flip.as_deref().unwrap_or("")),
+        )?
+    };
+    let img = transform.apply(img)?;
+
+    let cell_aspect = RenderSettings::cell_aspect(args, format)?;
+    let reserved = if format.has_layout() {
+        text_layout(args, true)?.reserved()
+    } else {
+        (0, 0)
+    };
+    let config = RenderSettings::from_args(args, img, format.cell_span(), cell_aspect, reserved);
+    // The resized image is converted to f32 for computing the cells
+    limits.check_f32(config.im_width, config.im_height)?;
 
     if args.debug {
         println!(
@@ -218,30 +778,24 @@ fn main() -> ExitCode {
             config.im_width, config.im_height
         );
     }
-    let img = config.src.resize_exact(
+    // Each cell is CELL_W x CELL_H pixels of the resized image
+    let img = source_fit(args, config.fit).apply(
+        &config.src,
         config.im_width,
         config.im_height,
-        utils::get_scaling(&args.scaling.unwrap_or("".to_owned())),
+        config.cell_aspect * CELL_H as f32 / CELL_W as f32,
     );
 
-    use std::time::Instant;
-
-    let (mut cells, cell_time) = {
+    let (cells, cell_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).map_err(anyhow::Error::msg)?;
         (cells, now.elapsed())
     };
 
-    let mut colored = false;
-
     let (computed, compute_time) = {
         let now = Instant::now();
         let computed = match config.render_mode {
-            RenderMode::Color => {
-                colored = true;
-                cells.compute(args.invert_cell)
-            }
-            RenderMode::NoColor => cells.compute(args.invert_cell),
+            RenderMode::Color | RenderMode::NoColor => cells.compute(args.invert_cell),
             RenderMode::PlainText => {
                 cells.compute_ab(&Color::WHITE, &Color::BLACK, args.invert_cell)
             }
@@ -249,20 +803,879 @@ fn main() -> ExitCode {
         (computed, now.elapsed())
     };
 
-    let (s, string_time) = {
-        let now = Instant::now();
-        let (img, _) = AsciiImageRenderer::render(
-            &computed,
-            colored,
-            Some(charsets::get_charset(
-                &args.charset.unwrap_or("".to_string()),
-            )),
-            args.transparency_t.unwrap_or(0.9),
+    Ok((
+        computed,
+        ImagePipeline {
+            config,
+            img,
+            cells,
+            cell_time,
+            compute_time,
+        },
+    ))
+}
+
+fn svg_settings(args: &CliArgs) -> SvgSettings {
+    let defaults = SvgSettings::default();
+    SvgSettings {
+        font_family: args.svg_font_family.clone().unwrap_or(defaults.font_family),
+        font_size: args.svg_font_size.unwrap_or(defaults.font_size),
+        cell_width: args.svg_cell_width.unwrap_or(defaults.cell_width),
+        line_height: args.svg_line_height.unwrap_or(defaults.line_height),
+    }
+}
+
+/// How the source is fitted into the output box with the fit mode
+fn source_fit(args: &CliArgs, mode: FitMode) -> Fit {
+    Fit {
+        mode,
+        gravity: fit::get_gravity(args.gravity.as_deref().unwrap_or("")),
+        background: Color::parse(args.background.as_deref().unwrap_or(""))
+            .unwrap_or(Color::TRANSPARENT),
+        filter: utils::get_scaling(args.scaling.as_deref().unwrap_or("")),
+    }
+}
+
+/// Image to show with a graphics protocol, sized to cover the same cells as the computed grid,
+/// with the size of the terminal's cells when it can be detected. \
+/// The source is fitted like it was for the grid, so that it is padded or cropped the same way.
+/// When the grid was not computed from an image, the image is reconstructed from its cells.
+fn graphics_image(
+    args: &CliArgs,
+    computed: &ComputedCellGrid,
+    pipeline: Option<&ImagePipeline>,
+    colored: bool,
+) -> RgbaImage {
+    let cell_size = terminal::detect_cell_size();
+    let (width, height) = outputs::graphics_size(computed.width(), computed.height(), cell_size);
+    let img = match pipeline {
+        Some(pipeline) => {
+            let (cell_w, cell_h) = cell_size.unwrap_or((GLYPH_W as f32, GLYPH_H as f32));
+            source_fit(args, pipeline.config.fit).apply(
+                &pipeline.config.src,
+                width,
+                height,
+                pipeline.config.cell_aspect * cell_h / cell_w,
+            )
+        }
+        None => computed
+            .to_cell_grid()
+            .to_image()
+            .resize_exact(width, height, FilterType::Nearest),
+    };
+    if colored {
+        img.into_rgba8()
+    } else {
+        img.grayscale().into_rgba8()
+    }
+}
+
+/// Options which take a value, read from the usage line of the help
+fn value_options() -> HashSet<String> {
+    let help = CliArgs::from_args(&["clir_rs"], &["--help"])
+        .err()
+        .map(|x| x.output)
+        .unwrap_or_default();
+    batch::value_options(&help)
+}
+
+/// Context of errors reading or loading the source
+fn source_error(args: &CliArgs) -> &'static str {
+    if args.load_grid {
+        "Failed to load grid"
+    } else if args.import_art {
+        "Failed to read unicode art"
+    } else {
+        "Failed to read image"
+    }
+}
+
+/// Loads the grid from the source, depending on whether it is a saved grid, unicode art or an image.
+/// The bytes are read from the source beforehand, so that they can be used for the output's cache key.
+fn load_source(
+    args: &CliArgs,
+    source: &str,
+    bytes: &[u8],
+    format: OutputFormat,
+) -> anyhow::Result<(ComputedCellGrid, Option<ImagePipeline>)> {
+    if args.load_grid {
+        if args.debug {
+            println!("Loading grid from '{:?}'", source);
+        }
+        let grid = grid_file::read_grid(&mut &bytes[..]).context(source_error(args))?;
+        Ok((grid, None))
+    } else if args.import_art {
+        if args.debug {
+            println!("Importing unicode art from '{:?}'", source);
+        }
+        let (grid, warnings) = import::import_art(&String::from_utf8_lossy(bytes));
+        if let ImportWarnings::UnknownCharacters(count) = warnings {
+            eprintln!(
+                "Warning: {} unknown characters were imported as empty cells",
+                count
+            );
+        }
+        Ok((grid, None))
+    } else {
+        let (computed, pipeline) =
+            compute_from_image(args, source, bytes, format).context(source_error(args))?;
+        Ok((computed, Some(pipeline)))
+    }
+}
+
+/// Renders the grid in the output format into out
+fn render_output<W: Write>(
+    args: &CliArgs,
+    computed: &ComputedCellGrid,
+    pipeline: Option<&ImagePipeline>,
+    format: OutputFormat,
+    colored: bool,
+    layout: &Layout,
+    out: &mut W,
+) -> io::Result<()> {
+    let charset = Some(charsets::get_charset(args.charset.as_deref().unwrap_or("")));
+    let transparency_t = args.transparency_t.unwrap_or(0.9);
+    match format {
+        OutputFormat::Ansi if layout.is_empty() => {
+            AsciiImageRenderer::render_to(out, computed, colored, charset, transparency_t)
+                .map(|_| ())
+        }
+        OutputFormat::Ansi => {
+            let (art, _) = AsciiImageRenderer::render(computed, colored, charset, transparency_t);
+            out.write_all(layout.apply(&art).as_bytes())
+        }
+        OutputFormat::Svg => {
+            let (svg, _) = SvgImageRenderer::render(
+                computed,
+                colored,
+                charset,
+                transparency_t,
+                &svg_settings(args),
+            );
+            out.write_all(svg.as_bytes())
+        }
+        OutputFormat::Png => {
+            let (png, _) = PngImageRenderer::render(
+                computed,
+                colored,
+                charset,
+                transparency_t,
+                args.png_scale.unwrap_or(1),
+            );
+            let mut bytes = Vec::new();
+            png.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .map_err(io::Error::other)
+                .and_then(|_| out.write_all(&bytes))
+        }
+        OutputFormat::Ans => {
+            let defaults = SauceMetadata::default();
+            let metadata = SauceMetadata {
+                title: args.sauce_title.clone().unwrap_or(defaults.title),
+                author: args.sauce_author.clone().unwrap_or(defaults.author),
+                group: args.sauce_group.clone().unwrap_or(defaults.group),
+                font: args.sauce_font.clone().unwrap_or(defaults.font),
+            };
+            out.write_all(&AnsImageRenderer::render(
+                computed,
+                colored,
+                transparency_t,
+                &metadata,
+                &ans::today(),
+            ))
+        }
+        OutputFormat::Irc => {
+            let (irc, _, truncated_lines) = IrcImageRenderer::render(
+                computed,
+                colored,
+                charset,
+                transparency_t,
+                args.irc_line_limit,
+            );
+            if truncated_lines > 0 {
+                eprintln!(
+                    "Warning: {} lines were truncated to fit the irc line limit",
+                    truncated_lines
+                );
+            }
+            out.write_all(irc.as_bytes())
+        }
+        OutputFormat::Codegen(language) => {
+            let (art, _) = AsciiImageRenderer::render(computed, colored, charset, transparency_t);
+            let settings = CodegenSettings {
+                name: args
+                    .codegen_name
+                    .clone()
+                    .unwrap_or(CodegenSettings::default().name),
+                lines: args.codegen_lines,
+            };
+            out.write_all(CodeGenerator::generate(&art, language, &settings).as_bytes())
+        }
+        OutputFormat::Sixel => {
+            let img = graphics_image(args, computed, pipeline, colored);
+            out.write_all(SixelImageRenderer::render(&img, transparency_t).as_bytes())
+                .and_then(|_| out.write_all(b"\n"))
+        }
+        OutputFormat::Kitty => {
+            let img = graphics_image(args, computed, pipeline, colored);
+            KittyImageRenderer::render(
+                &img,
+                kitty::get_kitty_format(args.kitty_format.as_deref().unwrap_or("")),
+                computed.width(),
+                computed.height(),
+            )
+            .map_err(io::Error::other)
+            .and_then(|kitty| out.write_all(kitty.as_bytes()))
+            .and_then(|_| out.write_all(b"\n"))
+        }
+        OutputFormat::Emoji => {
+            let emoji = EmojiImageRenderer::render(computed, colored, transparency_t);
+            if layout.is_empty() {
+                out.write_all(emoji.as_bytes())
+            } else {
+                out.write_all(layout.apply(&emoji).as_bytes())
+            }
+        }
+    }
+    .and_then(|_| out.flush())
+}
+
+/// Joins the messages of the error & its causes on a single line, skipping causes already included in the previous message
+fn error_chain(e: &anyhow::Error) -> String {
+    let mut messages: Vec<String> = Vec::new();
+    for cause in e.chain() {
+        let message = cause.to_string();
+        if !messages.last().is_some_and(|last| last.contains(&message)) {
+            messages.push(message);
+        }
+    }
+    messages.join(": ")
+}
+
+/// Renders every source into its own file in --output-dir, in parallel. \
+/// Failures are reported in the summary, without stopping the other files.
+fn run_batch(
+    args: &CliArgs,
+    settings: &Settings,
+    cache: Option<&Cache>,
+    format: OutputFormat,
+    colored: bool,
+    layout: &Layout,
+) -> ExitCode {
+    let Some(output_dir) = &args.output_dir else {
+        eprintln!("Fatal error: --output-dir is needed to render multiple sources");
+        return ExitCode::FAILURE;
+    };
+    let output_dir = PathBuf::from(utils::expand_path(output_dir));
+    let output_cache = cache.filter(|_| format.is_cacheable());
+    if let Err(e) = fs::create_dir_all(&output_dir) {
+        eprintln!(
+            "Fatal error while trying to create output dir {:?}: {:?}",
+            output_dir, e
         );
-        (img, now.elapsed())
+        return ExitCode::FAILURE;
+    }
+    for (option, set) in [
+        ("--output", args.output.is_some()),
+        ("--save-grid", args.save_grid.is_some()),
+        ("--save-cells", args.save_cells.is_some()),
+    ] {
+        if set {
+            eprintln!("Warning: {} is ignored when rendering a batch", option);
+        }
+    }
+
+    let template = args
+        .output_template
+        .as_deref()
+        .unwrap_or(batch::DEFAULT_TEMPLATE);
+    let sources = batch::expand_sources(
+        &args.source,
+        args.recursive,
+        !args.load_grid && !args.import_art,
+    );
+
+    // Output paths are picked beforehand, so that files which would overwrite each other are caught
+    let mut used: HashMap<PathBuf, String> = HashMap::new();
+    let jobs: Vec<(String, anyhow::Result<PathBuf>)> = sources
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| match source {
+            Ok(source) => {
+                let path = batch::output_name(template, &source, i + 1, format.extension())
+                    .map(|name| output_dir.join(name))
+                    .and_then(|path| match used.get(&path) {
+                        Some(other) => Err(anyhow::anyhow!(
+                            "Output {:?} is already used by '{}', add {{index}} to --output-template to keep both",
+                            path,
+                            other
+                        )),
+                        None => {
+                            used.insert(path.clone(), source.clone());
+                            Ok(path)
+                        }
+                    });
+                (source, path)
+            }
+            Err(e) => (String::new(), Err(e)),
+        })
+        .collect();
+
+    let results: Vec<(String, anyhow::Result<PathBuf>, Duration)> = jobs
+        .into_par_iter()
+        .map(|(source, path)| {
+            let now = Instant::now();
+            let result = path.and_then(|path| {
+                let bytes = read_source(args, &source, cache).context(source_error(args))?;
+                let key = output_key(args, settings, format, &source, &bytes);
+                let output = match output_cache.and_then(|cache| cache.get_output(&key)) {
+                    Some(output) => output,
+                    None => {
+                        let (computed, pipeline) = load_source(args, &source, &bytes, format)?;
+                        let layout = source_layout(
+                            args,
+                            layout,
+                            &source,
+                            &computed,
+                            pipeline.as_ref(),
+                            colored,
+                        );
+                        let mut output = Vec::new();
+                        render_output(
+                            args,
+                            &computed,
+                            pipeline.as_ref(),
+                            format,
+                            colored,
+                            &layout,
+                            &mut output,
+                        )?;
+                        if let Some(cache) = output_cache {
+                            cache.put_output(&key, &output)?;
+                        }
+                        output
+                    }
+                };
+                fs::write(&path, output)?;
+                Ok(path)
+            });
+            (source, result, now.elapsed())
+        })
+        .collect();
+
+    let mut failed = 0;
+    for (source, result, time) in &results {
+        match result {
+            Ok(path) => println!("ok      {} -> {} ({:.2?})", source, path.display(), time),
+            Err(e) => {
+                failed += 1;
+                if source.is_empty() {
+                    println!("failed  {}", error_chain(e));
+                } else {
+                    println!("failed  {}: {}", source, error_chain(e));
+                }
+            }
+        }
+    }
+    println!(
+        "Rendered {} of {} files, {} failed",
+        results.len() - failed,
+        results.len(),
+        failed
+    );
+
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Layout of ansi & emoji outputs from --align, --valign, --margin, --padding & --padding-color
+fn text_layout(args: &CliArgs, colored: bool) -> anyhow::Result<Layout> {
+    let spacing = |s: &Option<String>| -> anyhow::Result<Spacing> {
+        Ok(s.as_deref()
+            .map(Spacing::parse)
+            .transpose()?
+            .unwrap_or_default())
+    };
+    let (columns, rows) = RenderSettings::autodetected_size();
+    Ok(Layout {
+        align: layout::get_align(args.align.as_deref().unwrap_or("")).unwrap_or_default(),
+        vertical_align: layout::get_vertical_align(args.valign.as_deref().unwrap_or(""))
+            .unwrap_or_default(),
+        margin: spacing(&args.margin)?,
+        padding: spacing(&args.padding)?,
+        fill: args
+            .padding_color
+            .as_deref()
+            .and_then(Color::parse)
+            .filter(|color| colored && color.a > 0.0)
+            .map(Into::into),
+        border: layout::get_border_style(args.border.as_deref().unwrap_or("")),
+        // auto is filled in by source_layout, once the image is known
+        border_color: args
+            .border_color
+            .as_deref()
+            .and_then(Color::parse)
+            .filter(|color| colored && color.a > 0.0)
+            .map(Into::into),
+        title: args.title.clone(),
+        caption: args.caption.clone(),
+        columns,
+        rows,
+    })
+}
+
+/// Fills in the parts of the layout depending on the source: the placeholders of the title & caption, and --border-color auto
+fn source_layout(
+    args: &CliArgs,
+    layout: &Layout,
+    source: &str,
+    computed: &ComputedCellGrid,
+    pipeline: Option<&ImagePipeline>,
+    colored: bool,
+) -> Layout {
+    // Images use their size in pixels, grids their size in cells
+    let (width, height) = match pipeline {
+        Some(pipeline) => (
+            pipeline.config.src.width() as usize,
+            pipeline.config.src.height() as usize,
+        ),
+        None => (computed.width(), computed.height()),
+    };
+    let fill = |template: &String| {
+        template
+            .replace("{name}", &batch::source_name(source))
+            .replace("{stem}", &batch::source_stem(source))
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string())
+    };
+    Layout {
+        title: layout.title.as_ref().map(fill),
+        caption: layout.caption.as_ref().map(fill),
+        border_color: if colored && args.border_color.as_deref() == Some("auto") {
+            computed.dominant_color().map(Into::into)
+        } else {
+            layout.border_color
+        },
+        ..layout.clone()
+    }
+}
+
+/// Name of the stdout writer in the MultiWriter
+const STDOUT: &str = "stdout";
+
+/// Writer printing the output & saving it to --output in a single pass. Fails when the --output file cannot be created.
+fn output_writer(args: &CliArgs, format: OutputFormat) -> anyhow::Result<MultiWriter> {
+    let mut out = MultiWriter::new();
+    if !args.no_print {
+        if format.is_text() {
+            let passthrough =
+                passthrough::get_passthrough(args.passthrough.as_deref().unwrap_or(""))
+                    .unwrap_or_else(|| {
+                        if format.is_graphics() {
+                            passthrough::detect()
+                        } else {
+                            Passthrough::None
+                        }
+                    });
+            if format.is_ansi() && passthrough != Passthrough::None {
+                out.push(
+                    STDOUT,
+                    Box::new(PassthroughWriter::new(io::stdout(), passthrough)),
+                );
+            } else {
+                out.push(STDOUT, Box::new(io::stdout()));
+            }
+        } else if args.output.is_none() {
+            eprintln!(
+                "Warning: {:?} output cannot be printed, use --output to save it",
+                format
+            );
+        }
+    }
+    if let Some(path) = &args.output {
+        let expanded = utils::expand_path(path);
+        let file = File::create(&expanded)
+            .with_context(|| format!("Failed to create output file {:?}", expanded))?;
+        out.push(expanded, Box::new(BufWriter::new(file)));
+    }
+    Ok(out)
+}
+
+/// Reports the writers which failed, returning whether the output was fully written. \
+/// A closed pipe on stdout, eg. from `| head`, is not an error.
+fn report_write_errors(out: &mut MultiWriter, written: io::Result<()>) -> bool {
+    let errors = out.take_errors();
+    let mut ok = true;
+    // Without failed writers, the error comes from the renderer itself
+    if let (Err(e), true) = (written, errors.is_empty()) {
+        eprintln!("Failed to write output due to {:?}", e);
+        ok = false;
+    }
+    for (name, e) in errors {
+        if name == STDOUT && e.kind() == io::ErrorKind::BrokenPipe {
+            continue;
+        }
+        eprintln!("Failed to write output to {} due to {:?}", name, e);
+        ok = false;
+    }
+    ok
+}
+
+/// Same as argh::from_env, with support for `-` as the source
+fn parse_args() -> CliArgs {
+    let strings: Vec<String> = std::env::args().collect();
+    let cmd = std::path::Path::new(&strings[0])
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or(&strings[0]);
+    let args = batch::move_stdin_source(&strings[1..], &value_options());
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+    CliArgs::from_args(&[cmd], &args).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {} --help for more information.",
+                    early_exit.output, cmd
+                );
+                1
+            }
+        })
+    })
+}
+
+/// Fills the options which were not given on the command line from the settings, returning the effective settings. \
+/// Each switch has a negation, so that a switch set by the settings can be turned off on the command line.
+fn apply_settings(args: &mut CliArgs, base: Settings) -> anyhow::Result<Settings> {
+    macro_rules! apply {
+        (options: $($option:ident),*; switches: $($switch:ident / $negation:ident),*) => {{
+            $(
+                if args.$switch && args.$negation {
+                    anyhow::bail!(
+                        "--{} and --{} cannot be used together",
+                        stringify!($switch).replace('_', "-"),
+                        stringify!($negation).replace('_', "-")
+                    );
+                }
+            )*
+            let cli = Settings {
+                $($option: args.$option.clone(),)*
+                $($switch: (args.$switch || args.$negation).then_some(args.$switch),)*
+            };
+            let settings = base.merge(cli);
+            $(args.$option = settings.$option.clone();)*
+            $(args.$switch = settings.$switch.unwrap_or(false);)*
+            Ok(settings)
+        }};
+    }
+    apply!(
+        options: width, height, charset, scaling, transparency_t, format, svg_font_family, svg_font_size,
+            svg_cell_width, svg_line_height, png_scale, sauce_title, sauce_author, sauce_group, sauce_font,
+            irc_line_limit, codegen_name, kitty_format, passthrough, output_template, timeout,
+            connect_timeout, read_timeout, max_download, user_agent, cache_size, max_image_size,
+            max_decoded_size, fit, gravity, background, crop, rotate, flip, trim_tolerance, trim_padding,
+            cell_aspect, align, valign, margin, padding, padding_color, border, border_color, title, caption;
+        switches: no_autosize / autosize, no_keep_aspect / keep_aspect, no_color / color, plain_text / no_plain_text,
+            use_original_image_size / no_use_original_image_size, invert_cell / no_invert_cell,
+            codegen_lines / no_codegen_lines, recursive / no_recursive, no_cache / cache, trim / no_trim
+    )
+}
+
+/// Built-in defaults of the settings, the lowest layer of the effective settings. \
+/// The size is left unset, as it is fitted to the terminal unless --no-autosize is given.
+fn default_settings() -> Settings {
+    let svg = SvgSettings::default();
+    Settings {
+        no_autosize: Some(false),
+        no_keep_aspect: Some(false),
+        no_color: Some(false),
+        plain_text: Some(false),
+        use_original_image_size: Some(false),
+        charset: Some("classic".to_string()),
+        scaling: Some("linear".to_string()),
+        transparency_t: Some(0.9),
+        invert_cell: Some(false),
+        format: Some("ansi".to_string()),
+        svg_font_family: Some(svg.font_family),
+        svg_font_size: Some(svg.font_size),
+        svg_cell_width: Some(svg.cell_width),
+        svg_line_height: Some(svg.line_height),
+        png_scale: Some(1),
+        sauce_font: Some(SauceMetadata::default().font),
+        codegen_name: Some(CodegenSettings::default().name),
+        codegen_lines: Some(false),
+        kitty_format: Some("png".to_string()),
+        passthrough: Some("auto".to_string()),
+        output_template: Some(batch::DEFAULT_TEMPLATE.to_string()),
+        recursive: Some(false),
+        timeout: Some(fetch::DEFAULT_TIMEOUT.as_secs_f32()),
+        connect_timeout: Some(fetch::DEFAULT_CONNECT_TIMEOUT.as_secs_f32()),
+        read_timeout: Some(fetch::DEFAULT_READ_TIMEOUT.as_secs_f32()),
+        max_download: Some("50M".to_string()),
+        user_agent: Some(fetch::DEFAULT_USER_AGENT.to_string()),
+        no_cache: Some(false),
+        cache_size: Some("100M".to_string()),
+        max_image_size: Some(format!(
+            "{}x{}",
+            limits::DEFAULT_MAX_DIMENSION,
+            limits::DEFAULT_MAX_DIMENSION
+        )),
+        max_decoded_size: Some("512M".to_string()),
+        fit: Some("keep".to_string()),
+        gravity: Some("center".to_string()),
+        background: Some("transparent".to_string()),
+        trim: Some(false),
+        trim_tolerance: Some(Trim::DEFAULT_TOLERANCE),
+        trim_padding: Some(0),
+        cell_aspect: Some("auto".to_string()),
+        align: Some("left".to_string()),
+        valign: Some("top".to_string()),
+        margin: Some("0".to_string()),
+        padding: Some("0".to_string()),
+        padding_color: Some("none".to_string()),
+        border: Some("none".to_string()),
+        border_color: Some("none".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Loads the config file & environment variables into the args. Returns the effective settings, with the config path & preset used.
+fn load_settings(
+    args: &mut CliArgs,
+) -> anyhow::Result<(Settings, Option<PathBuf>, Option<String>)> {
+    let (path, file) = match &args.config {
+        Some(path) => {
+            let path = PathBuf::from(utils::expand_path(path));
+            if !path.is_file() {
+                anyhow::bail!("Config file {:?} does not exist", path);
+            }
+            let file = ConfigFile::load(&path)?;
+            (Some(path), file)
+        }
+        None => match config::default_path() {
+            Some(path) => {
+                let file = ConfigFile::load(&path)?;
+                (path.is_file().then_some(path), file)
+            }
+            None => (None, ConfigFile::default()),
+        },
+    };
+    let preset = args
+        .preset
+        .clone()
+        .or_else(|| std::env::var(config::PRESET_ENV).ok())
+        .filter(|x| !x.is_empty());
+    let env = Settings::from_env().context("Invalid environment variable")?;
+    let base = file.settings(preset.as_deref())?.merge(env);
+    Ok((apply_settings(args, base)?, path, preset))
+}
+
+fn main() -> ExitCode {
+    let before_cmd = Instant::now();
+    let mut args = parse_args();
+
+    let settings = match load_settings(&mut args) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
     };
+    if args.print_config {
+        let (settings, path, preset) = settings;
+        match path {
+            Some(path) => println!("# config file: {}", path.display()),
+            None => println!("# config file: none"),
+        }
+        if let Some(preset) = preset {
+            println!("# preset: {}", preset);
+        }
+        print!("{}", default_settings().merge(settings).to_toml());
+        return ExitCode::SUCCESS;
+    }
+    let (settings, _, _) = settings;
 
     if args.debug {
+        println!("Running with arguments: {:#?}", args);
+    }
+
+    let colored = !args.no_color && !args.plain_text;
+    let format = outputs::get_output_format(args.format.as_deref().unwrap_or(""));
+    let layout = match text_layout(&args, colored) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if !layout.is_empty() && !format.has_layout() {
+        eprintln!(
+            "Warning: --align, --valign, --margin, --padding, --border & --caption are ignored for {:?} output",
+            format
+        );
+    }
+
+    let cache = match open_cache(&args) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.source.is_empty() {
+        if args.clear_cache {
+            return ExitCode::SUCCESS;
+        }
+        eprintln!("Fatal error: no source was given");
+        return ExitCode::FAILURE;
+    }
+    if args.source.len() > 1 || args.output_dir.is_some() {
+        let code = run_batch(&args, &settings, cache.as_ref(), format, colored, &layout);
+        evict_cache(cache.as_ref(), args.debug);
+        eprint!("Command completed in: {:.2?}", before_cmd.elapsed());
+        return code;
+    }
+    let source = &args.source[0];
+
+    let bytes = match read_source(&args, source, cache.as_ref()).context(source_error(&args)) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let key = output_key(&args, &settings, format, source, &bytes);
+    // Saving the grid or cells needs the grid, which is not cached
+    let output_cache = cache.as_ref().filter(|_| format.is_cacheable());
+    let cached = output_cache
+        .filter(|_| args.save_grid.is_none() && args.save_cells.is_none())
+        .and_then(|cache| cache.get_output(&key));
+    if let Some(output) = cached {
+        if args.debug {
+            println!("Using cached output {}", key);
+        }
+        let mut out = match output_writer(&args, format) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Fatal error: {:?}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        let written = out.write_all(&output).and_then(|_| out.flush());
+        let ok = report_write_errors(&mut out, written);
+        evict_cache(cache.as_ref(), args.debug);
+        eprint!("Command completed in: {:.2?}", before_cmd.elapsed());
+        return if ok {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        };
+    }
+
+    let (computed, mut pipeline) = match load_source(&args, source, &bytes, format) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = &args.save_grid {
+        let expanded = utils::expand_path(path);
+        match grid_file::save_grid(&expanded, &computed) {
+            Ok(_) => {
+                if args.debug {
+                    println!("Saved grid to {:?}", expanded)
+                }
+            }
+            Err(e) => eprintln!("Failed to save grid to path at '{:?}' due to {:?}", path, e),
+        }
+    }
+
+    if let Some(path) = &args.save_cells {
+        let expanded = utils::expand_path(path);
+        match computed.to_cell_grid().save_as(&expanded) {
+            Ok(_) => {
+                if args.debug {
+                    println!("Saved cells to {:?}", expanded)
+                }
+            }
+            Err(e) => eprintln!(
+                "Failed to save cells to path at '{:?}' due to {:?}",
+                path, e
+            ),
+        }
+    }
+
+    let layout = source_layout(
+        &args,
+        &layout,
+        source,
+        &computed,
+        pipeline.as_ref(),
+        colored,
+    );
+    let mut out = match output_writer(&args, format) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("Fatal error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (written, string_time) = {
+        let now = Instant::now();
+        let written = match output_cache {
+            // Streamed while a copy is captured, which is cached once the whole output was written
+            Some(cache) => {
+                let mut capture = CaptureWriter::new(&mut out, cache::MAX_OUTPUT_SIZE);
+                let written = render_output(
+                    &args,
+                    &computed,
+                    pipeline.as_ref(),
+                    format,
+                    colored,
+                    &layout,
+                    &mut capture,
+                )
+                .and_then(|_| capture.flush());
+                if let (Ok(_), Some(output)) = (&written, capture.into_captured()) {
+                    if let Err(e) = cache.put_output(&key, &output) {
+                        eprintln!("Warning: failed to cache the output due to {:?}", e);
+                    }
+                }
+                written
+            }
+            None => render_output(
+                &args,
+                &computed,
+                pipeline.as_ref(),
+                format,
+                colored,
+                &layout,
+                &mut out,
+            ),
+        };
+        (written, now.elapsed())
+    };
+
+    let ok = report_write_errors(&mut out, written);
+    if let (true, true, Some(path)) = (ok, args.debug, &args.output) {
+        println!("Wrote output to {:?}", utils::expand_path(path))
+    }
+
+    if let (true, Some(pipeline)) = (args.debug, &mut pipeline) {
+        let ImagePipeline {
+            config,
+            img,
+            cells,
+            cell_time,
+            compute_time,
+        } = pipeline;
         match fs::create_dir("./clir_rs_debug/") {
             Ok(_) => {}
             Err(e) => eprintln!("Warning: failed to create debug output dir {:?}", e),
@@ -270,12 +1683,7 @@ fn main() -> ExitCode {
         cells.save_as("./clir_rs_debug/colored_cells.png").unwrap();
         cell::round_cells_with_ab(&mut cells.cells, &Color::WHITE, &Color::TRANSPARENT);
         cells.save_as("./clir_rs_debug/bw_cells.png").unwrap();
-    }
-    if !args.no_print {
-        println!("{}", s);
-    }
 
-    if args.debug {
         println!(
             "Source Image Size ({}x{}={}) | Final Image size ({}x{}={}) | Cells count: {} ({}x{}={})",
             config.src.width(),
@@ -291,32 +1699,18 @@ fn main() -> ExitCode {
         );
         println!(
             "Cell Generate Time: {:.2?} | Round Cell Pixels time: {:.2?} | String time: {:.2?} | Total compute time {:.2?}",
-            cell_time, compute_time, string_time, compute_time + string_time + cell_time
+            cell_time, compute_time, string_time, *compute_time + string_time + *cell_time
         );
+    } else if args.debug {
+        println!("String time: {:.2?}", string_time);
     }
+    evict_cache(cache.as_ref(), args.debug);
 
-    if let Some(path) = args.output {
-        let expanded = utils::expand_path(&path);
-        match File::create(&expanded) {
-            Ok(mut file) => match file.write_all(s.as_bytes()) {
-                Ok(_) => {
-                    if args.debug {
-                        println!("Wrote output to {:?}", utils::expand_path(&expanded))
-                    }
-                }
-
-                Err(e) => eprintln!(
-                    "Failed to write output to path at '{:?}' due to {:?}",
-                    path, e
-                ),
-            },
-            Err(e) => eprintln!(
-                "Failed to write output to path at '{:?}' due to {:?}",
-                path, e
-            ),
-        }
+    // Printed to stderr, so that piped output is only the render
+    eprint!("Command completed in: {:.2?}", before_cmd.elapsed());
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
     }
-
-    print!("Command completed in: {:.2?}", before_cmd.elapsed());
-    ExitCode::SUCCESS
 }
@@ -1,9 +1,230 @@
+use std::io;
 
-use crate::{ansi, cell::ComputedCellGrid, charsets};
+use crate::{ansi, cell::ComputedCellGrid, charsets, color::RGBColorU8};
+
+use self::codegen::CodegenLanguage;
+
+pub mod ans;
+pub mod bitmap_font;
+pub mod codegen;
+pub mod emoji;
+pub mod irc;
+pub mod kitty;
+pub mod png;
+pub mod sixel;
+pub mod svg;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Ansi,
+    Svg,
+    Png,
+    Ans,
+    Irc,
+    Emoji,
+    Codegen(codegen::CodegenLanguage),
+    Sixel,
+    Kitty,
+}
+
+impl OutputFormat {
+    /// Whether the output is text that can be printed to the console
+    pub fn is_text(&self) -> bool {
+        !matches!(self, OutputFormat::Png | OutputFormat::Ans)
+    }
+
+    /// Extension of files holding the output
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Ansi | OutputFormat::Irc | OutputFormat::Emoji => "txt",
+            OutputFormat::Svg => "svg",
+            OutputFormat::Png => "png",
+            OutputFormat::Ans => "ans",
+            OutputFormat::Codegen(CodegenLanguage::Rust) => "rs",
+            OutputFormat::Codegen(CodegenLanguage::C) => "h",
+            OutputFormat::Codegen(CodegenLanguage::Shell) => "sh",
+            OutputFormat::Sixel => "six",
+            OutputFormat::Kitty => "kitty",
+        }
+    }
+
+    /// Whether the output is shown with a graphics protocol instead of characters
+    pub fn is_graphics(&self) -> bool {
+        matches!(self, OutputFormat::Sixel | OutputFormat::Kitty)
+    }
+
+    /// Whether the output can be cached, .ans outputs embed the current date in their SAUCE record
+    pub fn is_cacheable(&self) -> bool {
+        !matches!(self, OutputFormat::Ans)
+    }
+
+    /// Whether the output contains escape sequences meant for the terminal
+    pub fn is_ansi(&self) -> bool {
+        matches!(self, OutputFormat::Ansi) || self.is_graphics()
+    }
+
+    /// Whether the output is lines of text for the terminal, which can be aligned & padded, see crate::layout
+    pub fn has_layout(&self) -> bool {
+        matches!(self, OutputFormat::Ansi | OutputFormat::Emoji)
+    }
+
+    /// Whether the output is shown in the terminal it is rendered in, so that it is sized for the terminal's cells
+    pub fn is_shown_in_terminal(&self) -> bool {
+        matches!(self, OutputFormat::Ansi | OutputFormat::Emoji) || self.is_graphics()
+    }
+
+    /// Number of terminal columns & rows each cell of the grid takes up in the output
+    pub fn cell_span(&self) -> usize {
+        match self {
+            OutputFormat::Emoji => emoji::EMOJI_WIDTH,
+            _ => 1,
+        }
+    }
+}
+
+pub fn get_output_format(name: &str) -> OutputFormat {
+    match name {
+        "svg" => OutputFormat::Svg,
+        "png" => OutputFormat::Png,
+        "ans" => OutputFormat::Ans,
+        "irc" => OutputFormat::Irc,
+        "emoji" => OutputFormat::Emoji,
+        "rust" => OutputFormat::Codegen(CodegenLanguage::Rust),
+        "c" => OutputFormat::Codegen(CodegenLanguage::C),
+        "sh" => OutputFormat::Codegen(CodegenLanguage::Shell),
+        "sixel" => OutputFormat::Sixel,
+        "kitty" => OutputFormat::Kitty,
+        _ => OutputFormat::Ansi,
+    }
+}
+
+/// Size in pixels of a grid of cells shown with a graphics protocol such as sixel or kitty, where each terminal cell is
+/// cell_size pixels. When the size of the terminal's cells is unknown, they are assumed to be GLYPH_W x GLYPH_H pixels,
+/// the size of the bundled bitmap font.
+pub fn graphics_size(width: usize, height: usize, cell_size: Option<(f32, f32)>) -> (u32, u32) {
+    let (cell_w, cell_h) =
+        cell_size.unwrap_or((bitmap_font::GLYPH_W as f32, bitmap_font::GLYPH_H as f32));
+    (
+        (width as f32 * cell_w).round() as u32,
+        (height as f32 * cell_h).round() as u32,
+    )
+}
+
+/// Looks up the character to use for a cell's bitmask in the charset. \
+/// Returns None when the charset does not have enough characters.
+pub(crate) fn cell_char(characters: &[char], bitmask: u8) -> Option<char> {
+    characters
+        .get(charsets::cell_bitmask_to_char_index(bitmask) as usize)
+        .copied()
+}
+
+/// Writes everything to all of its writers, so that a single render can be sent to multiple destinations at once. \
+/// A writer that fails is dropped and its error kept, while the others carry on, so that eg. a closed pipe on stdout
+/// does not stop the output file from being written. Writing only fails once every writer has failed.
+#[derive(Default)]
+pub struct MultiWriter {
+    writers: Vec<(String, Box<dyn io::Write>)>,
+    errors: Vec<(String, io::Error)>,
+}
+
+impl MultiWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a writer, named for reporting its errors
+    pub fn push(&mut self, name: impl Into<String>, writer: Box<dyn io::Write>) {
+        self.writers.push((name.into(), writer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writers.is_empty()
+    }
+
+    /// Names & errors of the writers which failed, in the order they failed
+    pub fn take_errors(&mut self) -> Vec<(String, io::Error)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn each(&mut self, mut f: impl FnMut(&mut dyn io::Write) -> io::Result<()>) -> io::Result<()> {
+        let had_writers = !self.writers.is_empty();
+        let mut i = 0;
+        while i < self.writers.len() {
+            match f(&mut self.writers[i].1) {
+                Ok(()) => i += 1,
+                Err(e) => {
+                    let (name, _) = self.writers.remove(i);
+                    self.errors.push((name, e));
+                }
+            }
+        }
+        if had_writers && self.writers.is_empty() {
+            return Err(io::Error::other("every output failed"));
+        }
+        Ok(())
+    }
+}
+
+impl io::Write for MultiWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.each(|writer| writer.write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.each(|writer| writer.flush())
+    }
+}
+
+/// Passes everything through to its writer while keeping a copy, so that the output can be streamed & cached at once. \
+/// The copy is dropped once it grows over max_size, so that large outputs are not kept in memory.
+pub struct CaptureWriter<W: io::Write> {
+    inner: W,
+    captured: Option<Vec<u8>>,
+    max_size: usize,
+}
+
+impl<W: io::Write> CaptureWriter<W> {
+    pub fn new(inner: W, max_size: usize) -> Self {
+        Self {
+            inner,
+            captured: Some(Vec::new()),
+            max_size,
+        }
+    }
+
+    /// Everything written, None when it grew over max_size
+    pub fn into_captured(self) -> Option<Vec<u8>> {
+        self.captured
+    }
+}
+
+impl<W: io::Write> io::Write for CaptureWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(captured) = &mut self.captured {
+            if captured.len() + written > self.max_size {
+                self.captured = None;
+            } else {
+                captured.extend_from_slice(&buf[..written]);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 pub struct AsciiImageRenderer;
 
 impl AsciiImageRenderer {
+    /// Renders the grid as unicode art. See render_to.
     pub fn render(
         grid: &ComputedCellGrid,
         colored: bool,
@@ -12,53 +233,74 @@ impl AsciiImageRenderer {
     ) -> (String, charsets::CharsetWarnings) {
         let capacity = (grid.cells.len() + grid.height())
             * ComputedCellGrid::UTF8_BYTE_SIZE
-            * if colored { 8 } else { 1 };
-        let mut s = String::with_capacity(capacity);
+            * if colored { 4 } else { 1 };
+        let mut bytes = Vec::with_capacity(capacity);
 
+        // Writing to a vec never fails
+        let warnings = Self::render_to(&mut bytes, grid, colored, charset, transparency_t).unwrap();
+        (String::from_utf8(bytes).unwrap(), warnings)
+    }
+
+    /// Renders the grid as unicode art, writing it to out one row at a time. \
+    /// When colored, SGR sequences are only emitted when the colors change, and the colors are reset at the end of every line.
+    pub fn render_to<W: io::Write>(
+        out: &mut W,
+        grid: &ComputedCellGrid,
+        colored: bool,
+        charset: Option<&str>,
+        transparency_t: f32,
+    ) -> io::Result<charsets::CharsetWarnings> {
         let characters: Vec<char> = charset.unwrap_or(charsets::BRAILLE).chars().collect();
         let mut missing_char: bool = false;
-        for i in 0..grid.cells.len() {
-            let cell = &grid.cells[i];
-            let index = charsets::cell_bitmask_to_char_index(cell.bitmask);
+        if grid.width() == 0 {
+            return Ok(charsets::CharsetWarnings::None);
+        }
 
-            let char_ = if index as usize > characters.len() {
-                missing_char = true;
-                '?'
-            } else {
-                characters[index as usize]
-                // '?'
-            };
+        let mut s = String::with_capacity(
+            (grid.width() + 1) * ComputedCellGrid::UTF8_BYTE_SIZE * if colored { 4 } else { 1 },
+        );
+        let mut sgr = ansi::SgrEmitter::new();
+        for row in grid.cells.chunks(grid.width()) {
+            s.clear();
+            for cell in row {
+                let char_ = cell_char(&characters, cell.bitmask).unwrap_or_else(|| {
+                    missing_char = true;
+                    '?'
+                });
 
-            if colored {
-                let fore = ansi::convert(cell.fore, true);
-                // print!("{:?}",cell.fore);
-                let back = ansi::convert(cell.back, true);
+                if colored {
+                    let fore: RGBColorU8 = cell.fore.into();
+                    let back: Option<RGBColorU8> = if cell.back.a < transparency_t {
+                        None
+                    } else {
+                        Some(cell.back.into())
+                    };
 
-                if cell.back.a < transparency_t {
-                    if cell.fore.a < transparency_t {
+                    if char_ == characters[0] || (back.is_none() && cell.fore.a < transparency_t) {
+                        // Nothing but the background is visible
+                        sgr.set_back(&mut s, back).unwrap();
                         s.push(characters[0]);
+                    } else if back == Some(fore) {
+                        // The glyph would be indistinguishable from the background
+                        sgr.set_back(&mut s, back).unwrap();
+                        s.push(' ');
                     } else {
-                        s.push_str(&fore.paint(char_.to_string()).to_string());
+                        sgr.set(&mut s, Some(fore), back).unwrap();
+                        s.push(char_);
                     }
                 } else {
-                    s.push_str(&fore.on(back).paint(char_.to_string()).to_string());
-                }
-            } else {
-                s.push(char_);
-            };
-
-            if (i + 1) % (grid.width()) == 0 {
-                s.push('\n');
+                    s.push(char_);
+                };
             }
+            sgr.reset(&mut s).unwrap();
+            s.push('\n');
+            out.write_all(s.as_bytes())?;
         }
 
-        (
-            s,
-            if missing_char {
-                charsets::CharsetWarnings::NotEnoughCharacters
-            } else {
-                charsets::CharsetWarnings::None
-            },
-        )
+        Ok(if missing_char {
+            charsets::CharsetWarnings::NotEnoughCharacters
+        } else {
+            charsets::CharsetWarnings::None
+        })
     }
 }
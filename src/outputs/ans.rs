@@ -0,0 +1,323 @@
+//! BBS style .ANS output. Cells are drawn with CP437 block characters & 16 color SGR attributes, followed by a SAUCE record.
+//!
+//! See https://www.acid.org/info/sauce/sauce.htm for the SAUCE specification.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    cell::{ComputedCell, ComputedCellGrid},
+    color::{Color, RGBColorU8},
+};
+
+/// The standard VGA palette, in the order of the SGR color codes. Colors 8-15 are the bright (bold) variants.
+pub const PALETTE: [RGBColorU8; 16] = [
+    rgb(0, 0, 0),
+    rgb(170, 0, 0),
+    rgb(0, 170, 0),
+    rgb(170, 85, 0),
+    rgb(0, 0, 170),
+    rgb(170, 0, 170),
+    rgb(0, 170, 170),
+    rgb(170, 170, 170),
+    rgb(85, 85, 85),
+    rgb(255, 85, 85),
+    rgb(85, 255, 85),
+    rgb(255, 255, 85),
+    rgb(85, 85, 255),
+    rgb(255, 85, 255),
+    rgb(85, 255, 255),
+    rgb(255, 255, 255),
+];
+
+const fn rgb(r: u8, g: u8, b: u8) -> RGBColorU8 {
+    RGBColorU8 { r, g, b }
+}
+
+/// Light gray on black, used when the output is not colored
+const DEFAULT_ATTRIBUTES: (u8, u8) = (7, 0);
+
+/// CP437 characters used to draw the cells, with the cell bitmask they cover. Shades only cover part of each pixel.
+const BLOCKS: [(u8, u8); 6] = [
+    (0x20, 0b0000_0000), // space
+    (0xdb, 0b1111_1111), // full block
+    (0xdf, 0b0000_1111), // upper half
+    (0xdc, 0b1111_0000), // lower half
+    (0xdd, 0b0101_0101), // left half
+    (0xde, 0b1010_1010), // right half
+];
+/// Light, medium & dark shades, with the number of pixels of the cell they are equivalent to
+const SHADES: [(u8, u32); 3] = [(0xb0, 2), (0xb1, 4), (0xb2, 6)];
+
+pub const SAUCE_LEN: usize = 128;
+/// Marks the end of the art, so that the SAUCE record is not displayed
+pub const EOF_CHAR: u8 = 0x1a;
+
+/// User provided fields of the SAUCE record
+#[derive(Debug, Clone, PartialEq)]
+pub struct SauceMetadata {
+    pub title: String,
+    pub author: String,
+    pub group: String,
+    /// Font the art is meant to be displayed with
+    pub font: String,
+}
+
+impl Default for SauceMetadata {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            author: String::new(),
+            group: String::new(),
+            font: "IBM VGA".to_string(),
+        }
+    }
+}
+
+/// A SAUCE record describing a character (ANSi) file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sauce {
+    pub metadata: SauceMetadata,
+    /// Creation date formatted as CCYYMMDD
+    pub date: String,
+    /// Size of the art, excluding the EOF character & SAUCE record
+    pub file_size: u32,
+    pub data_type: u8,
+    pub file_type: u8,
+    /// Width of the art in characters
+    pub width: u16,
+    /// Number of lines of the art
+    pub lines: u16,
+    pub flags: u8,
+}
+
+impl Sauce {
+    pub const DATA_TYPE_CHARACTER: u8 = 1;
+    pub const FILE_TYPE_ANSI: u8 = 1;
+
+    pub fn to_bytes(&self) -> [u8; SAUCE_LEN] {
+        let mut bytes = Vec::with_capacity(SAUCE_LEN);
+        bytes.extend_from_slice(b"SAUCE00");
+        bytes.extend(padded(&self.metadata.title, 35, b' '));
+        bytes.extend(padded(&self.metadata.author, 20, b' '));
+        bytes.extend(padded(&self.metadata.group, 20, b' '));
+        bytes.extend(padded(&self.date, 8, b' '));
+        bytes.extend_from_slice(&self.file_size.to_le_bytes());
+        bytes.push(self.data_type);
+        bytes.push(self.file_type);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.lines.to_le_bytes());
+        // TInfo3, TInfo4 & number of comment lines are unused
+        bytes.extend_from_slice(&[0; 5]);
+        bytes.push(self.flags);
+        bytes.extend(padded(&self.metadata.font, 22, 0));
+        bytes.try_into().unwrap()
+    }
+
+    /// Parses the SAUCE record at the end of the data, None if there is no record
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let record = data.get(data.len().checked_sub(SAUCE_LEN)?..)?;
+        if &record[0..7] != b"SAUCE00" {
+            return None;
+        }
+        let text = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&record[range])
+                .trim_end_matches([' ', '\0'])
+                .to_string()
+        };
+        let u16_at = |i: usize| u16::from_le_bytes([record[i], record[i + 1]]);
+        Some(Self {
+            metadata: SauceMetadata {
+                title: text(7..42),
+                author: text(42..62),
+                group: text(62..82),
+                font: text(106..128),
+            },
+            date: text(82..90),
+            file_size: u32::from_le_bytes(record[90..94].try_into().unwrap()),
+            data_type: record[94],
+            file_type: record[95],
+            width: u16_at(96),
+            lines: u16_at(98),
+            flags: record[105],
+        })
+    }
+}
+
+/// Truncates or pads the text to len bytes. Non ascii characters are replaced with '?' as SAUCE fields are CP437.
+fn padded(text: &str, len: usize, pad: u8) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c as u8
+            } else {
+                b'?'
+            }
+        })
+        .take(len)
+        .collect();
+    bytes.resize(len, pad);
+    bytes
+}
+
+/// Today's date formatted as CCYYMMDD
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+    date_from_days(days)
+}
+
+/// Date of the days since 1970-01-01 formatted as CCYYMMDD
+pub fn date_from_days(days: i64) -> String {
+    // Converts days since 1970-01-01 to a civil date. See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+/// Finds the nearest color in the first `count` colors of the palette, returning its index & squared distance
+fn nearest_palette(color: RGBColorU8, count: usize) -> (u8, u32) {
+    PALETTE[..count]
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+            (i as u8, d(color.r, p.r) + d(color.g, p.g) + d(color.b, p.b))
+        })
+        .min_by_key(|(_, dist)| *dist)
+        .unwrap()
+}
+
+/// Picks the CP437 character which best matches the bitmask
+fn nearest_block(bitmask: u8) -> u8 {
+    let blocks = BLOCKS
+        .iter()
+        .map(|(c, mask)| (*c, (bitmask ^ mask).count_ones() * 2));
+    // Shades are only a rough match, hence the penalty to prefer solid blocks
+    let shades = SHADES
+        .iter()
+        .map(|(c, pixels)| (*c, bitmask.count_ones().abs_diff(*pixels) * 2 + 3));
+    blocks
+        .chain(shades)
+        .min_by_key(|(_, cost)| *cost)
+        .unwrap()
+        .0
+}
+
+/// Picks the character & (fore, back) palette indexes for the cell. \
+/// As only the first 8 colors can be used as background, the fore & back colors are swapped when that gives a closer match.
+fn cell_attributes(cell: &ComputedCell, colored: bool, transparency_t: f32) -> (u8, (u8, u8)) {
+    if !colored {
+        return (nearest_block(cell.bitmask), DEFAULT_ATTRIBUTES);
+    }
+
+    let to_rgb = |c: Color| -> RGBColorU8 {
+        if c.a < transparency_t {
+            PALETTE[0]
+        } else {
+            c.into()
+        }
+    };
+    let (fore, back) = (to_rgb(cell.fore), to_rgb(cell.back));
+
+    let (fore_i, fore_d) = nearest_palette(fore, 16);
+    let (back_i, back_d) = nearest_palette(back, 8);
+    let (swapped_fore_i, swapped_fore_d) = nearest_palette(back, 16);
+    let (swapped_back_i, swapped_back_d) = nearest_palette(fore, 8);
+
+    if swapped_fore_d + swapped_back_d < fore_d + back_d {
+        (
+            nearest_block(!cell.bitmask),
+            (swapped_fore_i, swapped_back_i),
+        )
+    } else {
+        (nearest_block(cell.bitmask), (fore_i, back_i))
+    }
+}
+
+/// Writes the SGR sequence to change from the current attributes to the new ones
+fn write_attributes(out: &mut Vec<u8>, current: Option<(u8, u8)>, (fore, back): (u8, u8)) {
+    let mut codes: Vec<String> = Vec::new();
+    let bold = fore >= 8;
+    match current {
+        // Bold can only be turned off with a reset, which also resets the colors
+        Some((cur_fore, cur_back)) if cur_fore < 8 || bold => {
+            if bold && cur_fore < 8 {
+                codes.push("1".to_string());
+            }
+            if fore % 8 != cur_fore % 8 {
+                codes.push(format!("3{}", fore % 8));
+            }
+            if back != cur_back {
+                codes.push(format!("4{}", back));
+            }
+        }
+        _ => {
+            codes.push("0".to_string());
+            if bold {
+                codes.push("1".to_string());
+            }
+            codes.push(format!("3{}", fore % 8));
+            codes.push(format!("4{}", back));
+        }
+    }
+    if !codes.is_empty() {
+        out.extend_from_slice(format!("\x1b[{}m", codes.join(";")).as_bytes());
+    }
+}
+
+pub struct AnsImageRenderer;
+
+impl AnsImageRenderer {
+    /// Renders the grid as CP437 encoded ANSi art, followed by the EOF character & a SAUCE record dated with date,
+    /// formatted as CCYYMMDD. \
+    /// Lines are ended with CRLF.
+    pub fn render(
+        grid: &ComputedCellGrid,
+        colored: bool,
+        transparency_t: f32,
+        metadata: &SauceMetadata,
+        date: &str,
+    ) -> Vec<u8> {
+        let mut out = Vec::with_capacity(grid.cells.len() * 4);
+        let mut current: Option<(u8, u8)> = None;
+
+        if grid.width() > 0 {
+            for row in grid.cells.chunks(grid.width()) {
+                for cell in row {
+                    let (c, attributes) = cell_attributes(cell, colored, transparency_t);
+                    if current != Some(attributes) {
+                        write_attributes(&mut out, current, attributes);
+                        current = Some(attributes);
+                    }
+                    out.push(c);
+                }
+                out.extend_from_slice(b"\r\n");
+            }
+        }
+        out.extend_from_slice(b"\x1b[0m");
+
+        let sauce = Sauce {
+            metadata: metadata.clone(),
+            date: date.to_string(),
+            file_size: out.len() as u32,
+            data_type: Sauce::DATA_TYPE_CHARACTER,
+            file_type: Sauce::FILE_TYPE_ANSI,
+            width: grid.width() as u16,
+            lines: grid.height() as u16,
+            flags: 0,
+        };
+        out.push(EOF_CHAR);
+        out.extend_from_slice(&sauce.to_bytes());
+        out
+    }
+}
@@ -0,0 +1,130 @@
+//! A tiny monospace bitmap font covering the characters used by the bundled charsets (braille patterns & block elements).
+//!
+//! Glyphs are generated from the codepoint instead of being stored, since both unicode blocks are purely geometric.
+//! Each glyph is GLYPH_W x GLYPH_H pixels, which keeps the 1:2 aspect of a terminal cell (CELL_W:CELL_H).
+
+pub const GLYPH_W: u32 = 8;
+pub const GLYPH_H: u32 = 16;
+
+/// A glyph stored as one byte per row, where the most significant bit is the leftmost pixel
+pub type Glyph = [u8; GLYPH_H as usize];
+
+/// Glyph drawn for characters that are not covered by the font (an outlined box)
+pub const REPLACEMENT: Glyph = [
+    0x00, 0x00, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7e, 0x00, 0x00,
+];
+
+/// Returns the glyph for the character, None if the font does not cover it.
+pub fn glyph(c: char) -> Option<Glyph> {
+    let code = c as u32;
+    match code {
+        0x20 => Some([0; GLYPH_H as usize]),
+        0x2580..=0x259f => Some(block_glyph(code)),
+        0x2800..=0x28ff => Some(braille_glyph((code - 0x2800) as u8)),
+        _ => None,
+    }
+}
+
+/// Returns the glyph for the character, falling back to REPLACEMENT when it is not covered.
+pub fn glyph_or_replacement(c: char) -> Glyph {
+    glyph(c).unwrap_or(REPLACEMENT)
+}
+
+/// Checks whether the pixel at (x, y) is set in the glyph
+pub fn is_set(glyph: &Glyph, x: u32, y: u32) -> bool {
+    glyph[y as usize] & (0x80 >> x) != 0
+}
+
+/// Braille dots are drawn as 2x2 squares, with gaps between them like in a terminal.
+fn braille_glyph(dots: u8) -> Glyph {
+    // (column, row) of each dot, in the order of the bits of the braille codepoint offset
+    const DOT_POSITIONS: [(u32, u32); 8] = [
+        (0, 0),
+        (0, 1),
+        (0, 2),
+        (1, 0),
+        (1, 1),
+        (1, 2),
+        (0, 3),
+        (1, 3),
+    ];
+
+    let mut glyph = [0; GLYPH_H as usize];
+    for (bit, (col, row)) in DOT_POSITIONS.iter().enumerate() {
+        if dots & (1 << bit) == 0 {
+            continue;
+        }
+        let row_bits = 0b0110_0000_u8 >> (col * 4);
+        let y = row * 4 + 1;
+        glyph[y as usize] |= row_bits;
+        glyph[y as usize + 1] |= row_bits;
+    }
+    glyph
+}
+
+fn block_glyph(code: u32) -> Glyph {
+    const UPPER_LEFT: u8 = 1;
+    const UPPER_RIGHT: u8 = 2;
+    const LOWER_LEFT: u8 = 4;
+    const LOWER_RIGHT: u8 = 8;
+    // Quadrants used by U+2596 to U+259F
+    const QUADRANTS: [u8; 10] = [
+        LOWER_LEFT,
+        LOWER_RIGHT,
+        UPPER_LEFT,
+        UPPER_LEFT | LOWER_LEFT | LOWER_RIGHT,
+        UPPER_LEFT | LOWER_RIGHT,
+        UPPER_LEFT | UPPER_RIGHT | LOWER_LEFT,
+        UPPER_LEFT | UPPER_RIGHT | LOWER_RIGHT,
+        UPPER_RIGHT,
+        UPPER_RIGHT | LOWER_LEFT,
+        UPPER_RIGHT | LOWER_LEFT | LOWER_RIGHT,
+    ];
+
+    let pixel: Box<dyn Fn(u32, u32) -> bool> = match code {
+        // Upper half
+        0x2580 => Box::new(|_, y| y < GLYPH_H / 2),
+        // Lower 1/8 to 8/8 (full block)
+        0x2581..=0x2588 => {
+            let eighths = code - 0x2580;
+            Box::new(move |_, y| y >= GLYPH_H - eighths * GLYPH_H / 8)
+        }
+        // Left 7/8 to 1/8
+        0x2589..=0x258f => {
+            let eighths = 8 - (code - 0x2588);
+            Box::new(move |x, _| x < eighths * GLYPH_W / 8)
+        }
+        // Right half
+        0x2590 => Box::new(|x, _| x >= GLYPH_W / 2),
+        // Light, medium & dark shades
+        0x2591 => Box::new(|x, y| x % 2 == 0 && y % 2 == 0),
+        0x2592 => Box::new(|x, y| (x + y) % 2 == 0),
+        0x2593 => Box::new(|x, y| x % 2 == 0 || y % 2 == 0),
+        // Upper 1/8
+        0x2594 => Box::new(|_, y| y < GLYPH_H / 8),
+        // Right 1/8
+        0x2595 => Box::new(|x, _| x >= GLYPH_W - GLYPH_W / 8),
+        _ => {
+            let quadrants = QUADRANTS[(code - 0x2596) as usize];
+            Box::new(move |x, y| {
+                let quadrant = match (x < GLYPH_W / 2, y < GLYPH_H / 2) {
+                    (true, true) => UPPER_LEFT,
+                    (false, true) => UPPER_RIGHT,
+                    (true, false) => LOWER_LEFT,
+                    (false, false) => LOWER_RIGHT,
+                };
+                quadrants & quadrant != 0
+            })
+        }
+    };
+
+    let mut glyph = [0; GLYPH_H as usize];
+    for (y, row) in glyph.iter_mut().enumerate() {
+        for x in 0..GLYPH_W {
+            if pixel(x, y as u32) {
+                *row |= 0x80 >> x;
+            }
+        }
+    }
+    glyph
+}
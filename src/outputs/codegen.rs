@@ -0,0 +1,154 @@
+//! Generates source code embedding rendered art, so that it can be shown by other programs without reading any file.
+//!
+//! Escaping differs per language, especially for the ESC bytes of the SGR sequences & the non ascii characters of the art.
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodegenLanguage {
+    /// A Rust `const` item
+    Rust,
+    /// A C header with string literals
+    C,
+    /// A POSIX shell script printing the art with `printf`
+    Shell,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenSettings {
+    /// Name of the generated constant. Characters that are not valid in an identifier are replaced with '_'.
+    pub name: String,
+    /// Emits an array with one string per line, instead of a single string
+    pub lines: bool,
+}
+
+impl Default for CodegenSettings {
+    fn default() -> Self {
+        Self {
+            name: "ART".to_string(),
+            lines: false,
+        }
+    }
+}
+
+/// Turns the name into a valid identifier in all supported languages
+pub fn identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// Escapes the text for a Rust string literal. Non ascii characters are kept as is, as Rust sources are UTF-8.
+pub fn escape_rust(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => s.push_str("\\\\"),
+            '"' => s.push_str("\\\""),
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if c.is_ascii_control() => write!(s, "\\x{:02x}", c as u32).unwrap(),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+/// Escapes the text for a C string literal. \
+/// Every byte outside of printable ascii is written as an octal escape, as the source charset of C compilers is not always UTF-8.
+/// Octal is used rather than hex, since a hex escape would also consume any hex digit following it.
+pub fn escape_c(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            // Avoids trigraphs
+            b'?' => s.push_str("\\?"),
+            b'\n' => s.push_str("\\n"),
+            b' '..=b'~' => s.push(b as char),
+            b => write!(s, "\\{:03o}", b).unwrap(),
+        }
+    }
+    s
+}
+
+/// Escapes the text for the format of `printf` within single quotes. \
+/// Control bytes are written as octal escapes, which printf expands. Non ascii characters are kept as is.
+pub fn escape_printf(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => s.push_str("\\\\"),
+            '%' => s.push_str("%%"),
+            '\'' => s.push_str("'\\''"),
+            '\n' => s.push_str("\\n"),
+            c if c.is_ascii_control() => write!(s, "\\{:03o}", c as u32).unwrap(),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+pub struct CodeGenerator;
+
+impl CodeGenerator {
+    /// Generates the source code embedding the art, which is usually the output of AsciiImageRenderer.
+    pub fn generate(art: &str, language: CodegenLanguage, settings: &CodegenSettings) -> String {
+        let name = identifier(&settings.name);
+        let lines: Vec<&str> = art.strip_suffix('\n').unwrap_or(art).split('\n').collect();
+        let mut s = String::new();
+
+        match language {
+            CodegenLanguage::Rust => {
+                if settings.lines {
+                    writeln!(s, "pub const {}: &[&str] = &[", name).unwrap();
+                    for line in lines {
+                        writeln!(s, "    \"{}\",", escape_rust(line)).unwrap();
+                    }
+                    s.push_str("];\n");
+                } else {
+                    // concat! keeps one line of art per line of code
+                    writeln!(s, "pub const {}: &str = concat!(", name).unwrap();
+                    for line in lines {
+                        writeln!(s, "    \"{}\\n\",", escape_rust(line)).unwrap();
+                    }
+                    s.push_str(");\n");
+                }
+            }
+            CodegenLanguage::C => {
+                let guard = format!("{}_H", name.to_ascii_uppercase());
+                writeln!(s, "#ifndef {}\n#define {}\n", guard, guard).unwrap();
+                if settings.lines {
+                    writeln!(s, "#define {}_LINES {}\n", name, lines.len()).unwrap();
+                    writeln!(s, "static const char *const {}[] = {{", name).unwrap();
+                    for line in lines {
+                        writeln!(s, "    \"{}\",", escape_c(line)).unwrap();
+                    }
+                    s.push_str("};\n");
+                } else {
+                    // Adjacent literals are concatenated by the compiler
+                    write!(s, "static const char {}[] =", name).unwrap();
+                    for line in lines {
+                        write!(s, "\n    \"{}\\n\"", escape_c(line)).unwrap();
+                    }
+                    s.push_str(";\n");
+                }
+                writeln!(s, "\n#endif /* {} */", guard).unwrap();
+            }
+            CodegenLanguage::Shell => {
+                s.push_str("#!/bin/sh\n");
+                for line in lines {
+                    writeln!(s, "printf '{}\\n'", escape_printf(line)).unwrap();
+                }
+            }
+        }
+        s
+    }
+}
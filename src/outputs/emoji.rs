@@ -0,0 +1,102 @@
+//! Emoji square output, for chat platforms that do not render ANSI escape sequences.
+//!
+//! Each cell is split into a top & bottom half of CELL_W x CELL_W pixels, so that every emoji covers a square region of the image.
+//! As emoji are two columns wide, a grid of w x h cells becomes 2w columns & 2h rows, keeping the aspect ratio of the ansi output.
+
+use crate::{
+    cell::{ComputedCell, ComputedCellGrid, CELL_W},
+    color::{Color, RGBColorU8},
+};
+
+/// The colored squares, with their approximate color as drawn by common emoji fonts
+pub const SQUARES: [(char, RGBColorU8); 9] = [
+    ('\u{1f7e5}', rgb(0xdd, 0x2e, 0x44)), // red
+    ('\u{1f7e7}', rgb(0xf4, 0x90, 0x0c)), // orange
+    ('\u{1f7e8}', rgb(0xfd, 0xcb, 0x58)), // yellow
+    ('\u{1f7e9}', rgb(0x78, 0xb1, 0x59)), // green
+    ('\u{1f7e6}', rgb(0x55, 0xac, 0xee)), // blue
+    ('\u{1f7ea}', rgb(0xaa, 0x8e, 0xd6)), // purple
+    ('\u{1f7eb}', rgb(0xc1, 0x69, 0x4f)), // brown
+    (BLACK, rgb(0x31, 0x37, 0x3d)),
+    (WHITE, rgb(0xe6, 0xe7, 0xe8)),
+];
+
+pub const BLACK: char = '\u{2b1b}';
+pub const WHITE: char = '\u{2b1c}';
+/// How much more a difference in hue counts than a difference in lightness
+const CHROMA_WEIGHT: f32 = 8.0;
+/// Number of terminal columns taken by each emoji
+pub const EMOJI_WIDTH: usize = 2;
+
+const fn rgb(r: u8, g: u8, b: u8) -> RGBColorU8 {
+    RGBColorU8 { r, g, b }
+}
+
+/// Finds the square nearest to the color. \
+/// Differences in hue are weighted more than differences in lightness, as there are no gray squares,
+/// and a plain rgb distance would otherwise turn most grays purple or green.
+pub fn nearest_square(color: RGBColorU8) -> char {
+    SQUARES
+        .iter()
+        .map(|(c, p)| {
+            let diff = [
+                color.r as f32 - p.r as f32,
+                color.g as f32 - p.g as f32,
+                color.b as f32 - p.b as f32,
+            ];
+            let luma = 0.299 * diff[0] + 0.587 * diff[1] + 0.114 * diff[2];
+            let chroma: f32 = diff.iter().map(|d| (d - luma).powi(2)).sum();
+            (*c, 3.0 * luma * luma + CHROMA_WEIGHT * chroma)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+        .0
+}
+
+/// Bitmask of the pixels in each half of a cell
+const HALVES: [u8; 2] = [0b0000_1111, 0b1111_0000];
+const HALF_LEN: u32 = (CELL_W * CELL_W) as u32;
+
+/// Average color of the pixels of the cell covered by the half mask
+fn average_color(cell: &ComputedCell, half: u8) -> Color {
+    let fore_pixels = (cell.bitmask & half).count_ones();
+    cell.back
+        .lerp(cell.fore, fore_pixels as f32 / HALF_LEN as f32)
+}
+
+pub struct EmojiImageRenderer;
+
+impl EmojiImageRenderer {
+    /// Renders the grid as lines of emoji squares, each picked as the square nearest to the average color of its region. \
+    /// Regions more transparent than transparency_t are drawn as white squares, matching the background of most chats.
+    /// When not colored, regions where most pixels are set are drawn black, the rest white.
+    pub fn render(grid: &ComputedCellGrid, colored: bool, transparency_t: f32) -> String {
+        let mut s = String::with_capacity(grid.cells.len() * 2 * 4 + grid.height() * 2);
+
+        if grid.width() > 0 {
+            for row in grid.cells.chunks(grid.width()) {
+                for half in HALVES {
+                    for cell in row {
+                        let square = if !colored {
+                            if (cell.bitmask & half).count_ones() * 2 > HALF_LEN {
+                                BLACK
+                            } else {
+                                WHITE
+                            }
+                        } else {
+                            let color = average_color(cell, half);
+                            if color.a < transparency_t {
+                                WHITE
+                            } else {
+                                nearest_square(color.into())
+                            }
+                        };
+                        s.push(square);
+                    }
+                    s.push('\n');
+                }
+            }
+        }
+        s
+    }
+}
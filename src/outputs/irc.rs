@@ -0,0 +1,159 @@
+//! IRC output, colored with mIRC's ^C color codes.
+//!
+//! See https://modern.ircdocs.horse/formatting.html#colors for the color codes.
+
+use std::fmt::Write;
+
+use crate::{cell::ComputedCellGrid, charsets, color::RGBColorU8};
+
+use super::cell_char;
+
+pub const COLOR: char = '\x03';
+
+/// The 99 color palette of mIRC. Colors 0-15 are the classic colors, 16-98 the extended ones.
+pub const PALETTE: [u32; 99] = [
+    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00, 0xffff00,
+    0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x7f7f7f, 0xd2d2d2, 0x470000, 0x472100,
+    0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047,
+    0x47002a, 0x740000, 0x743a00, 0x747400, 0x517400, 0x007400, 0x007449, 0x007474, 0x004074,
+    0x000074, 0x4b0074, 0x740074, 0x740045, 0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500,
+    0x00b571, 0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b, 0xff0000, 0xff8c00,
+    0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff,
+    0xff0098, 0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff,
+    0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc, 0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c,
+    0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3, 0x000000, 0x131313,
+    0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+/// Finds the index of the nearest color in the palette
+pub fn nearest_color(color: RGBColorU8) -> u8 {
+    PALETTE
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| {
+            let d = |a: u8, shift: u32| (a as i32 - ((**p >> shift) & 0xff) as i32).pow(2);
+            d(color.r, 16) + d(color.g, 8) + d(color.b, 0)
+        })
+        .unwrap()
+        .0 as u8
+}
+
+/// Keeps track of the colors currently set in the line, so that color codes are only emitted when they change. \
+/// A color of None is the client's default color.
+#[derive(Debug, Default)]
+struct ColorState {
+    fore: Option<u8>,
+    back: Option<u8>,
+}
+
+impl ColorState {
+    /// Writes the codes needed to change to the new colors into out. \
+    /// Colors are always written with two digits, so that they are not confused with the text that follows.
+    fn set(&mut self, out: &mut String, fore: Option<u8>, back: Option<u8>) {
+        if fore == self.fore && back == self.back {
+            return;
+        }
+        // A lone ^C is the only way to go back to the default colors
+        if (fore.is_none() && self.fore.is_some()) || (back.is_none() && self.back.is_some()) {
+            out.push(COLOR);
+            self.fore = None;
+            self.back = None;
+            if fore.is_none() && back.is_none() {
+                return;
+            }
+        }
+        // The back color can only be set together with the fore color, which is white by default
+        let fore = fore.unwrap_or(0);
+        match back {
+            Some(back) if Some(back) != self.back => {
+                write!(out, "{}{:02},{:02}", COLOR, fore, back).unwrap()
+            }
+            _ if Some(fore) != self.fore => write!(out, "{}{:02}", COLOR, fore).unwrap(),
+            _ => {}
+        }
+        self.fore = Some(fore);
+        self.back = back;
+    }
+}
+
+pub struct IrcImageRenderer;
+
+impl IrcImageRenderer {
+    /// Renders the grid as lines of text colored with mIRC color codes, mapping each color to the nearest color in the palette. \
+    /// Each line starts with the default colors, as IRC clients reset the colors for every message.
+    ///
+    /// When line_limit is set, lines are truncated so that they are at most line_limit bytes long (excluding the newline).
+    /// Returns (output, charset warnings, number of truncated lines)
+    pub fn render(
+        grid: &ComputedCellGrid,
+        colored: bool,
+        charset: Option<&str>,
+        transparency_t: f32,
+        line_limit: Option<usize>,
+    ) -> (String, charsets::CharsetWarnings, usize) {
+        let characters: Vec<char> = charset.unwrap_or(charsets::BRAILLE).chars().collect();
+        let mut missing_char = false;
+        let mut truncated_lines = 0;
+        let mut s = String::with_capacity(grid.cells.len() * ComputedCellGrid::UTF8_BYTE_SIZE);
+
+        if grid.width() > 0 {
+            let mut line = String::new();
+            let mut cell_text = String::new();
+            for row in grid.cells.chunks(grid.width()) {
+                line.clear();
+                let mut state = ColorState::default();
+                for cell in row {
+                    let char_ = cell_char(&characters, cell.bitmask).unwrap_or_else(|| {
+                        missing_char = true;
+                        '?'
+                    });
+
+                    cell_text.clear();
+                    if colored {
+                        let fore = nearest_color(cell.fore.into());
+                        let back = if cell.back.a < transparency_t {
+                            None
+                        } else {
+                            Some(nearest_color(cell.back.into()))
+                        };
+
+                        if char_ == characters[0]
+                            || (back.is_none() && cell.fore.a < transparency_t)
+                        {
+                            // Nothing but the background is visible
+                            state.set(&mut cell_text, state.fore.or(Some(fore)), back);
+                            cell_text.push(characters[0]);
+                        } else if back == Some(fore) {
+                            // The glyph would be indistinguishable from the background
+                            state.set(&mut cell_text, state.fore.or(Some(fore)), back);
+                            cell_text.push(' ');
+                        } else {
+                            state.set(&mut cell_text, Some(fore), back);
+                            cell_text.push(char_);
+                        }
+                    } else {
+                        cell_text.push(char_);
+                    }
+
+                    if line_limit.is_some_and(|limit| line.len() + cell_text.len() > limit) {
+                        truncated_lines += 1;
+                        break;
+                    }
+                    line.push_str(&cell_text);
+                }
+                s.push_str(&line);
+                s.push('\n');
+            }
+        }
+
+        (
+            s,
+            if missing_char {
+                charsets::CharsetWarnings::NotEnoughCharacters
+            } else {
+                charsets::CharsetWarnings::None
+            },
+            truncated_lines,
+        )
+    }
+}
@@ -0,0 +1,89 @@
+//! Kitty graphics protocol output, which shows the image at the terminal's pixel resolution.
+//!
+//! See https://sw.kovidgoyal.net/kitty/graphics-protocol/ for the protocol.
+
+use std::{fmt::Write, io::Cursor};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{ImageFormat, RgbaImage};
+
+/// Maximum size of the base64 payload of each escape sequence
+pub const CHUNK_SIZE: usize = 4096;
+
+/// How the image data is transmitted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KittyFormat {
+    /// PNG compressed, usually much smaller
+    Png,
+    /// Raw 32 bit RGBA pixels, which avoids encoding & decoding the png
+    Rgba,
+}
+
+impl KittyFormat {
+    /// Value of the `f` control key
+    pub fn code(&self) -> u32 {
+        match self {
+            KittyFormat::Png => 100,
+            KittyFormat::Rgba => 32,
+        }
+    }
+}
+
+pub fn get_kitty_format(name: &str) -> KittyFormat {
+    match name {
+        "rgba" => KittyFormat::Rgba,
+        _ => KittyFormat::Png,
+    }
+}
+
+pub struct KittyImageRenderer;
+
+impl KittyImageRenderer {
+    /// Encodes the image as kitty graphics escape sequences, displayed over columns x rows cells.
+    pub fn render(
+        img: &RgbaImage,
+        format: KittyFormat,
+        columns: usize,
+        rows: usize,
+    ) -> image::ImageResult<String> {
+        let mut control = format!("a=T,f={},c={},r={},q=2", format.code(), columns, rows);
+        let data = match format {
+            KittyFormat::Png => {
+                let mut bytes = Vec::new();
+                img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+                bytes
+            }
+            KittyFormat::Rgba => {
+                // Raw pixels need their size, as it cannot be read from the data
+                write!(control, ",s={},v={}", img.width(), img.height()).unwrap();
+                img.as_raw().clone()
+            }
+        };
+        Ok(Self::transmit(&control, &data))
+    }
+
+    /// Splits the base64 encoded data into chunks of at most CHUNK_SIZE bytes, each in its own escape sequence. \
+    /// The control keys are only sent with the first chunk, and `m=1` marks that more chunks follow.
+    pub fn transmit(control: &str, data: &[u8]) -> String {
+        let encoded = STANDARD.encode(data);
+        // CHUNK_SIZE is a multiple of 4, so that every chunk is valid base64 on its own
+        let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(CHUNK_SIZE).collect();
+        let mut s = String::with_capacity(encoded.len() + chunks.len() * 16 + control.len());
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = if i + 1 < chunks.len() { 1 } else { 0 };
+            s.push_str("\x1b_G");
+            if i == 0 {
+                write!(s, "{},", control).unwrap();
+            }
+            write!(s, "m={};", more).unwrap();
+            // Base64 is always ascii
+            s.push_str(std::str::from_utf8(chunk).unwrap());
+            s.push_str("\x1b\\");
+        }
+        if chunks.is_empty() {
+            write!(s, "\x1b_G{},m=0;\x1b\\", control).unwrap();
+        }
+        s
+    }
+}
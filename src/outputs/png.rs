@@ -0,0 +1,87 @@
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    cell::ComputedCellGrid,
+    charsets,
+    color::{Color, RGBColorU8},
+};
+
+use super::{
+    bitmap_font::{self, GLYPH_H, GLYPH_W},
+    cell_char,
+};
+
+pub struct PngImageRenderer;
+
+impl PngImageRenderer {
+    /// Draws the grid the way a terminal would show it, using the bundled bitmap font. \
+    /// Each cell becomes a GLYPH_W x GLYPH_H block of pixels, multiplied by scale.
+    ///
+    /// When colored is false, the glyphs are drawn white on black.
+    pub fn render(
+        grid: &ComputedCellGrid,
+        colored: bool,
+        charset: Option<&str>,
+        transparency_t: f32,
+        scale: u32,
+    ) -> (RgbaImage, charsets::CharsetWarnings) {
+        let characters: Vec<char> = charset.unwrap_or(charsets::BRAILLE).chars().collect();
+        let mut missing_char = false;
+        let scale = scale.max(1);
+        let cell_w = GLYPH_W * scale;
+        let cell_h = GLYPH_H * scale;
+
+        let mut img = RgbaImage::new(grid.width() as u32 * cell_w, grid.height() as u32 * cell_h);
+
+        for (i, cell) in grid.cells.iter().enumerate() {
+            let char_ = cell_char(&characters, cell.bitmask).unwrap_or_else(|| {
+                missing_char = true;
+                '?'
+            });
+            let glyph = bitmap_font::glyph_or_replacement(char_);
+
+            let (fore, back) = if colored {
+                (
+                    to_pixel(cell.fore, transparency_t),
+                    to_pixel(cell.back, transparency_t),
+                )
+            } else {
+                (
+                    to_pixel(Color::WHITE, transparency_t),
+                    to_pixel(Color::BLACK, transparency_t),
+                )
+            };
+
+            let origin_x = (i % grid.width()) as u32 * cell_w;
+            let origin_y = (i / grid.width()) as u32 * cell_h;
+            for y in 0..cell_h {
+                for x in 0..cell_w {
+                    let pixel = if bitmap_font::is_set(&glyph, x / scale, y / scale) {
+                        fore
+                    } else {
+                        back
+                    };
+                    img.put_pixel(origin_x + x, origin_y + y, pixel);
+                }
+            }
+        }
+
+        (
+            img,
+            if missing_char {
+                charsets::CharsetWarnings::NotEnoughCharacters
+            } else {
+                charsets::CharsetWarnings::None
+            },
+        )
+    }
+}
+
+/// Converts the color to an opaque pixel, or a fully transparent one when its alpha is below transparency_t
+fn to_pixel(color: Color, transparency_t: f32) -> Rgba<u8> {
+    if color.a < transparency_t {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let rgb: RGBColorU8 = color.into();
+    Rgba([rgb.r, rgb.g, rgb.b, 255])
+}
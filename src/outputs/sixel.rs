@@ -0,0 +1,184 @@
+//! Sixel graphics output, which shows the image at the terminal's pixel resolution instead of as characters.
+//!
+//! See https://vt100.net/docs/vt3xx-gp/chapter14.html for the format.
+
+use std::{collections::HashMap, fmt::Write};
+
+use image::RgbaImage;
+
+/// Maximum number of color registers used. Most terminals support at least 256.
+pub const MAX_COLORS: usize = 256;
+/// Number of pixel rows encoded by each sixel character
+const SIXEL_H: u32 = 6;
+
+type Rgb = [u8; 3];
+
+/// Picks a palette of at most max_colors colors for the weighted colors with median cut. \
+/// When there are few enough colors, they are used as is, in the order they were given.
+pub fn quantize(colors: &[(Rgb, u32)], max_colors: usize) -> Vec<Rgb> {
+    if colors.len() <= max_colors {
+        return colors.iter().map(|(c, _)| *c).collect();
+    }
+
+    let mut boxes: Vec<Vec<(Rgb, u32)>> = vec![colors.to_vec()];
+    while boxes.len() < max_colors {
+        // Splits the box with the widest range of any channel
+        let Some((box_i, channel, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .flat_map(|(i, b)| {
+                (0..3).map(move |ch| {
+                    let (min, max) = b.iter().fold((u8::MAX, u8::MIN), |(min, max), (c, _)| {
+                        (min.min(c[ch]), max.max(c[ch]))
+                    });
+                    (i, ch, max - min)
+                })
+            })
+            .max_by_key(|(i, ch, range)| (*range, std::cmp::Reverse((*i, *ch))))
+        else {
+            break;
+        };
+
+        let mut b = boxes.swap_remove(box_i);
+        b.sort_by_key(|(c, _)| c[channel]);
+        // Splits at the weighted median, keeping at least one color on each side
+        let total: u64 = b.iter().map(|(_, n)| *n as u64).sum();
+        let mut acc = 0;
+        let mut split = 1;
+        for (i, (_, n)) in b.iter().enumerate().take(b.len() - 1) {
+            acc += *n as u64;
+            split = i + 1;
+            if acc * 2 >= total {
+                break;
+            }
+        }
+        let upper = b.split_off(split);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|b| {
+            let total: u64 = b.iter().map(|(_, n)| *n as u64).sum::<u64>().max(1);
+            let avg = |ch: usize| {
+                (b.iter().map(|(c, n)| c[ch] as u64 * *n as u64).sum::<u64>() / total) as u8
+            };
+            [avg(0), avg(1), avg(2)]
+        })
+        .collect()
+}
+
+fn nearest(palette: &[Rgb], color: Rgb) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| {
+            (0..3)
+                .map(|ch| (p[ch] as i32 - color[ch] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .unwrap()
+        .0
+}
+
+/// Writes the sixel characters, compressing runs of more than 3 of the same character
+fn write_rle(out: &mut String, sixels: &[u8]) {
+    let mut i = 0;
+    while i < sixels.len() {
+        let c = sixels[i];
+        let run = sixels[i..].iter().take_while(|x| **x == c).count();
+        if run > 3 {
+            write!(out, "!{}{}", run, c as char).unwrap();
+        } else {
+            for _ in 0..run {
+                out.push(c as char);
+            }
+        }
+        i += run;
+    }
+}
+
+/// Converts 0-255 to the 0-100 range used by sixel color registers
+fn percent(v: u8) -> u32 {
+    (v as u32 * 100 + 127) / 255
+}
+
+pub struct SixelImageRenderer;
+
+impl SixelImageRenderer {
+    /// Encodes the image as a sixel sequence, quantized to at most MAX_COLORS colors. \
+    /// Pixels more transparent than transparency_t are not drawn, leaving the terminal's background visible.
+    pub fn render(img: &RgbaImage, transparency_t: f32) -> String {
+        let (width, height) = img.dimensions();
+        let alpha_t = (transparency_t * 255.0).round() as u8;
+        let visible = |x: u32, y: u32| -> Option<Rgb> {
+            let p = img.get_pixel(x, y).0;
+            (p[3] >= alpha_t).then_some([p[0], p[1], p[2]])
+        };
+
+        // Colors in the order they appear, with how often they are used
+        let mut counts: HashMap<Rgb, usize> = HashMap::new();
+        let mut colors: Vec<(Rgb, u32)> = Vec::new();
+        for (x, y, _) in img.enumerate_pixels() {
+            if let Some(c) = visible(x, y) {
+                let i = *counts.entry(c).or_insert_with(|| {
+                    colors.push((c, 0));
+                    colors.len() - 1
+                });
+                colors[i].1 += 1;
+            }
+        }
+        let palette = quantize(&colors, MAX_COLORS);
+        let mut registers: HashMap<Rgb, usize> = HashMap::new();
+
+        // P2 = 1 keeps pixels that are not drawn transparent
+        let mut s = String::from("\x1bP0;1;0q");
+        write!(s, "\"1;1;{};{}", width, height).unwrap();
+        for (i, c) in palette.iter().enumerate() {
+            write!(
+                s,
+                "#{};2;{};{};{}",
+                i,
+                percent(c[0]),
+                percent(c[1]),
+                percent(c[2])
+            )
+            .unwrap();
+        }
+
+        let mut band: Vec<Option<Vec<u8>>> = vec![None; palette.len()];
+        for band_y in (0..height).step_by(SIXEL_H as usize) {
+            for y in band_y..(band_y + SIXEL_H).min(height) {
+                for x in 0..width {
+                    let Some(c) = visible(x, y) else { continue };
+                    let register = *registers.entry(c).or_insert_with(|| nearest(&palette, c));
+                    let sixels = band[register].get_or_insert_with(|| vec![0; width as usize]);
+                    sixels[x as usize] |= 1 << (y - band_y);
+                }
+            }
+
+            let mut first = true;
+            for (register, sixels) in band.iter_mut().enumerate() {
+                let Some(sixels) = sixels.take() else {
+                    continue;
+                };
+                // Each color is drawn over the same band, returning to its start with '$'
+                if !first {
+                    s.push('$');
+                }
+                first = false;
+                write!(s, "#{}", register).unwrap();
+                let used = sixels.iter().rposition(|x| *x != 0).map_or(0, |x| x + 1);
+                let chars: Vec<u8> = sixels[..used].iter().map(|x| x + b'?').collect();
+                write_rle(&mut s, &chars);
+            }
+            if band_y + SIXEL_H < height {
+                s.push('-');
+            }
+        }
+        s.push_str("\x1b\\");
+        s
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::Write;
+
+use crate::{
+    cell::{ComputedCell, ComputedCellGrid},
+    charsets,
+    color::RGBColorU8,
+};
+
+use super::cell_char;
+
+/// Layout options for the svg output. All sizes are in svg user units (px).
+pub struct SvgSettings {
+    pub font_family: String,
+    pub font_size: f32,
+    /// Width of a single character cell
+    pub cell_width: f32,
+    /// Height of a single row of characters
+    pub line_height: f32,
+}
+
+impl Default for SvgSettings {
+    fn default() -> Self {
+        Self {
+            font_family: "monospace".to_string(),
+            font_size: 14.0,
+            cell_width: 8.4,
+            line_height: 17.0,
+        }
+    }
+}
+
+pub struct SvgImageRenderer;
+
+impl SvgImageRenderer {
+    /// Renders the grid as an svg document. Each row becomes a `<text>` element, with a `<tspan>` for every run of the same fore color.
+    /// Background colors are drawn as `<rect>`s behind the text, merging neighbouring cells with the same color.
+    pub fn render(
+        grid: &ComputedCellGrid,
+        colored: bool,
+        charset: Option<&str>,
+        transparency_t: f32,
+        settings: &SvgSettings,
+    ) -> (String, charsets::CharsetWarnings) {
+        let characters: Vec<char> = charset.unwrap_or(charsets::BRAILLE).chars().collect();
+        let mut missing_char = false;
+
+        let width = grid.width() as f32 * settings.cell_width;
+        let height = grid.height() as f32 * settings.line_height;
+
+        let mut s = String::new();
+        writeln!(
+            s,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = num(width),
+            h = num(height)
+        )
+        .unwrap();
+
+        if grid.width() > 0 {
+            if colored {
+                s.push_str("<g shape-rendering=\"crispEdges\">\n");
+                for (y, row) in grid.cells.chunks(grid.width()).enumerate() {
+                    Self::write_backgrounds(&mut s, row, y, transparency_t, settings);
+                }
+                s.push_str("</g>\n");
+            }
+
+            writeln!(
+                s,
+                r#"<g font-family="{}" font-size="{}" xml:space="preserve">"#,
+                escape(&settings.font_family),
+                num(settings.font_size)
+            )
+            .unwrap();
+            for (y, row) in grid.cells.chunks(grid.width()).enumerate() {
+                Self::write_text_row(
+                    &mut s,
+                    row,
+                    y,
+                    &characters,
+                    colored,
+                    transparency_t,
+                    settings,
+                    &mut missing_char,
+                );
+            }
+            s.push_str("</g>\n");
+        }
+        s.push_str("</svg>\n");
+
+        (
+            s,
+            if missing_char {
+                charsets::CharsetWarnings::NotEnoughCharacters
+            } else {
+                charsets::CharsetWarnings::None
+            },
+        )
+    }
+
+    fn write_backgrounds(
+        s: &mut String,
+        row: &[ComputedCell],
+        y: usize,
+        transparency_t: f32,
+        settings: &SvgSettings,
+    ) {
+        let mut x = 0;
+        while x < row.len() {
+            if row[x].back.a < transparency_t {
+                x += 1;
+                continue;
+            }
+            let color: RGBColorU8 = row[x].back.into();
+            let start = x;
+            while x < row.len()
+                && row[x].back.a >= transparency_t
+                && RGBColorU8::from(row[x].back) == color
+            {
+                x += 1;
+            }
+            writeln!(
+                s,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                num(start as f32 * settings.cell_width),
+                num(y as f32 * settings.line_height),
+                num((x - start) as f32 * settings.cell_width),
+                num(settings.line_height),
+                color.hex()
+            )
+            .unwrap();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_text_row(
+        s: &mut String,
+        row: &[ComputedCell],
+        y: usize,
+        characters: &[char],
+        colored: bool,
+        transparency_t: f32,
+        settings: &SvgSettings,
+        missing_char: &mut bool,
+    ) {
+        // Glyphs are placed on the baseline, which sits roughly 80% of the font size below the top of the line box
+        let baseline = y as f32 * settings.line_height
+            + (settings.line_height - settings.font_size) / 2.0
+            + settings.font_size * 0.8;
+        // Tspans are written without whitespace between them, as it would be preserved as text
+        write!(s, r#"<text y="{}">"#, num(baseline)).unwrap();
+
+        // (fill, x positions, characters) of the current tspan
+        let mut run: Option<(Option<RGBColorU8>, String, String)> = None;
+        for (x, cell) in row.iter().enumerate() {
+            let char_ = cell_char(characters, cell.bitmask).unwrap_or_else(|| {
+                *missing_char = true;
+                '?'
+            });
+
+            // Nothing to draw when the glyph is blank or its fore color is transparent
+            if char_ == ' ' || (colored && cell.fore.a < transparency_t) {
+                if let Some(r) = run.take() {
+                    write_tspan(s, r);
+                }
+                continue;
+            }
+
+            let fill = if colored {
+                Some(RGBColorU8::from(cell.fore))
+            } else {
+                None
+            };
+            if run.as_ref().is_some_and(|(f, _, _)| *f != fill) {
+                write_tspan(s, run.take().unwrap());
+            }
+            let (_, xs, text) = run.get_or_insert_with(|| (fill, String::new(), String::new()));
+            if !xs.is_empty() {
+                xs.push(' ');
+            }
+            xs.push_str(&num(x as f32 * settings.cell_width));
+            text.push_str(&escape(&char_.to_string()));
+        }
+        if let Some(r) = run.take() {
+            write_tspan(s, r);
+        }
+        s.push_str("</text>\n");
+    }
+}
+
+fn write_tspan(s: &mut String, (fill, xs, text): (Option<RGBColorU8>, String, String)) {
+    match fill {
+        Some(color) => write!(
+            s,
+            r#"<tspan x="{}" fill="{}">{}</tspan>"#,
+            xs,
+            color.hex(),
+            text
+        ),
+        None => write!(s, r#"<tspan x="{}">{}</tspan>"#, xs, text),
+    }
+    .unwrap();
+}
+
+/// Formats a coordinate with at most 2 decimal places, hiding the noise from f32 multiplication
+fn num(value: f32) -> String {
+    let s = format!("{:.2}", value);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Escapes the characters that are not allowed as is in xml text & attributes
+fn escape(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            _ => s.push(c),
+        }
+    }
+    s
+}
@@ -0,0 +1,160 @@
+//! Passthrough of escape sequences through terminal multiplexers (tmux & GNU screen), which otherwise intercept
+//! the sequences they do not understand, such as the sixel & kitty graphics protocols.
+
+use std::io;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+/// Maximum length of each DCS string accepted by screen
+pub const SCREEN_CHUNK_SIZE: usize = 768;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Passthrough {
+    None,
+    /// Wraps sequences in `ESC P tmux; ... ESC \`, with every ESC inside doubled.
+    /// Requires `set -g allow-passthrough on` in tmux 3.3 and later.
+    Tmux,
+    /// Wraps sequences in `ESC P ... ESC \`, split into chunks that fit screen's limit
+    Screen,
+}
+
+/// Looks up the passthrough by name, None for unknown values so that it can be detected instead
+pub fn get_passthrough(name: &str) -> Option<Passthrough> {
+    match name {
+        "tmux" => Some(Passthrough::Tmux),
+        "screen" => Some(Passthrough::Screen),
+        "none" => Some(Passthrough::None),
+        _ => None,
+    }
+}
+
+/// Detects the multiplexer the program runs in from the environment
+pub fn detect() -> Passthrough {
+    if std::env::var_os("TMUX").is_some() {
+        Passthrough::Tmux
+    } else if std::env::var_os("STY").is_some() {
+        Passthrough::Screen
+    } else {
+        Passthrough::None
+    }
+}
+
+/// Wraps a single complete escape sequence for the multiplexer
+pub fn wrap(sequence: &[u8], passthrough: Passthrough) -> Vec<u8> {
+    let mut out = Vec::with_capacity(sequence.len() + 16);
+    match passthrough {
+        Passthrough::None => out.extend_from_slice(sequence),
+        Passthrough::Tmux => {
+            out.extend_from_slice(b"\x1bPtmux;");
+            for b in sequence {
+                if *b == ESC {
+                    out.push(ESC);
+                }
+                out.push(*b);
+            }
+            out.extend_from_slice(b"\x1b\\");
+        }
+        Passthrough::Screen => {
+            let mut start = 0;
+            while start < sequence.len() {
+                let mut end = (start + SCREEN_CHUNK_SIZE).min(sequence.len());
+                // The string terminator of the sequence would end the chunk early, so it is split across chunks
+                if let Some(i) = sequence[start..end].windows(2).position(|x| x == b"\x1b\\") {
+                    end = start + i + 1;
+                }
+                out.extend_from_slice(b"\x1bP");
+                out.extend_from_slice(&sequence[start..end]);
+                out.extend_from_slice(b"\x1b\\");
+                start = end;
+            }
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Ground,
+    /// After ESC
+    Escape,
+    /// Control sequence, ended by a final byte
+    Csi,
+    /// DCS, APC, OSC, PM & SOS strings, ended by ST (or BEL for OSC)
+    String,
+}
+
+/// Writer wrapping every escape sequence written to it for the multiplexer, while text is written as is. \
+/// Sequences may be split across writes, an incomplete sequence is kept until it is completed.
+pub struct PassthroughWriter<W: io::Write> {
+    inner: W,
+    passthrough: Passthrough,
+    state: State,
+    sequence: Vec<u8>,
+}
+
+impl<W: io::Write> PassthroughWriter<W> {
+    pub fn new(inner: W, passthrough: Passthrough) -> Self {
+        Self {
+            inner,
+            passthrough,
+            state: State::Ground,
+            sequence: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: io::Write> io::Write for PassthroughWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.passthrough == Passthrough::None {
+            return self.inner.write(buf);
+        }
+
+        let mut out = Vec::with_capacity(buf.len() + 16);
+        for &b in buf {
+            let complete = match self.state {
+                State::Ground => {
+                    if b == ESC {
+                        self.state = State::Escape;
+                        self.sequence.push(b);
+                    } else {
+                        out.push(b);
+                    }
+                    false
+                }
+                State::Escape => {
+                    self.sequence.push(b);
+                    match b {
+                        b'[' => self.state = State::Csi,
+                        b'P' | b'_' | b']' | b'^' | b'X' => self.state = State::String,
+                        _ => {}
+                    }
+                    self.state == State::Escape
+                }
+                State::Csi => {
+                    self.sequence.push(b);
+                    (0x40..=0x7e).contains(&b)
+                }
+                State::String => {
+                    let prev = self.sequence.last().copied();
+                    self.sequence.push(b);
+                    (b == b'\\' && prev == Some(ESC)) || (b == BEL && self.sequence[1] == b']')
+                }
+            };
+            if complete {
+                out.extend(wrap(&self.sequence, self.passthrough));
+                self.sequence.clear();
+                self.state = State::Ground;
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,187 @@
+//! Detection of the terminal's cell aspect ratio, so that images keep their proportions with fonts narrower or wider than 1:2.
+//!
+//! The pixel size is read from TIOCGWINSZ when the terminal fills in `ws_xpixel` & `ws_ypixel`, otherwise it is
+//! queried with the `CSI 16 t` (cell size) & `CSI 14 t` (text area size) reports.
+//! The queries are followed by a `CSI c` device attributes request, which every terminal answers, so that reading can
+//! stop as soon as its answer arrives rather than waiting for reports the terminal does not send.
+
+use std::sync::OnceLock;
+
+use crate::cell::{CELL_H, CELL_W};
+
+/// Width / height of a terminal cell when it cannot be detected, matching the CELL_W x CELL_H pixels of each cell
+pub const DEFAULT_CELL_ASPECT: f32 = CELL_W as f32 / CELL_H as f32;
+/// How long to wait for the terminal to answer the queries, when it does not answer the device attributes request
+#[cfg(unix)]
+const QUERY_TIMEOUT_MS: i32 = 300;
+
+/// Parses an aspect ratio given as a number (eg. "0.45") or as "width:height" / "width/height" (eg. "9:20")
+pub fn parse_cell_aspect(s: &str) -> Option<f32> {
+    let s = s.trim();
+    let aspect = match s.split_once([':', '/']) {
+        Some((w, h)) => w.trim().parse::<f32>().ok()? / h.trim().parse::<f32>().ok()?,
+        None => s.parse().ok()?,
+    };
+    (aspect.is_finite() && aspect > 0.0).then_some(aspect)
+}
+
+/// Size of a cell in pixels as (width, height), from a size in pixels divided into columns & rows. None when any is 0.
+pub fn cell_size_from_pixels(cols: u32, rows: u32, width: u32, height: u32) -> Option<(f32, f32)> {
+    if cols == 0 || rows == 0 || width == 0 || height == 0 {
+        return None;
+    }
+    Some((width as f32 / cols as f32, height as f32 / rows as f32))
+}
+
+/// Cell aspect from a size in pixels, divided into columns & rows. None when any is 0.
+pub fn aspect_from_pixels(cols: u32, rows: u32, width: u32, height: u32) -> Option<f32> {
+    cell_size_from_pixels(cols, rows, width, height).map(|(w, h)| w / h)
+}
+
+/// Sizes in pixels read from the terminal's reports to `CSI 16 t` & `CSI 14 t`, as (width, height)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SizeReports {
+    /// From `CSI 6 ; height ; width t`
+    pub cell: Option<(u32, u32)>,
+    /// From `CSI 4 ; height ; width t`
+    pub text_area: Option<(u32, u32)>,
+}
+
+/// Finds the size reports in the bytes read from the terminal, ignoring anything else
+pub fn parse_size_reports(bytes: &[u8]) -> SizeReports {
+    let mut reports = SizeReports::default();
+    let text = String::from_utf8_lossy(bytes);
+    for part in text.split("\x1b[").skip(1) {
+        let Some(end) = part.find('t') else { continue };
+        let params: Vec<u32> = match part[..end].split(';').map(|x| x.parse()).collect() {
+            Ok(params) => params,
+            Err(_) => continue,
+        };
+        match params[..] {
+            [6, height, width] => reports.cell = Some((width, height)),
+            [4, height, width] => reports.text_area = Some((width, height)),
+            _ => {}
+        }
+    }
+    reports
+}
+
+/// Whether the bytes read from the terminal contain its answer to the `CSI c` device attributes request, `CSI ? ... c`
+pub fn has_device_attributes(bytes: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(bytes);
+    text.split("\x1b[?").skip(1).any(|part| {
+        part.find(|c: char| !c.is_ascii_digit() && c != ';')
+            .is_some_and(|end| part[end..].starts_with('c'))
+    })
+}
+
+/// Detects the size in pixels of the cells of the terminal on stdout, as (width, height).
+/// Only queried once, as querying can wait on the terminal.
+pub fn detect_cell_size() -> Option<(f32, f32)> {
+    static DETECTED: OnceLock<Option<(f32, f32)>> = OnceLock::new();
+    *DETECTED.get_or_init(detect)
+}
+
+/// Detects the cell aspect of the terminal on stdout, see detect_cell_size
+pub fn detect_cell_aspect() -> Option<f32> {
+    detect_cell_size().map(|(width, height)| width / height)
+}
+
+#[cfg(unix)]
+fn detect() -> Option<(f32, f32)> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    let (cols, rows) = (size.ws_col as u32, size.ws_row as u32);
+    cell_size_from_pixels(cols, rows, size.ws_xpixel as u32, size.ws_ypixel as u32).or_else(|| {
+        let reports = query_size_reports()?;
+        match (reports.cell, reports.text_area) {
+            (Some((width, height)), _) => cell_size_from_pixels(1, 1, width, height),
+            (None, Some((width, height))) => cell_size_from_pixels(cols, rows, width, height),
+            (None, None) => None,
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn detect() -> Option<(f32, f32)> {
+    None
+}
+
+/// Writes the size queries to the controlling terminal, and reads its answers until the device attributes arrive or the
+/// timeout passes. Echo & line buffering are turned off meanwhile, so that the answers are not printed, and anything
+/// left unread is discarded before they are restored, so that late answers do not show up at the shell prompt.
+#[cfg(unix)]
+fn query_size_reports() -> Option<SizeReports> {
+    use std::{
+        fs::OpenOptions,
+        io::{IsTerminal, Read, Write},
+        os::fd::AsRawFd,
+        time::Instant,
+    };
+
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let fd = tty.as_raw_fd();
+
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return None;
+    }
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 0;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+
+    let mut answer = Vec::new();
+    if tty
+        .write_all(b"\x1b[16t\x1b[14t\x1b[c")
+        .and_then(|_| tty.flush())
+        .is_ok()
+    {
+        let start = Instant::now();
+        let mut buf = [0u8; 64];
+        loop {
+            let remaining = QUERY_TIMEOUT_MS - start.elapsed().as_millis() as i32;
+            if remaining <= 0 {
+                break;
+            }
+            let mut poll = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll, 1, remaining) } <= 0 {
+                break;
+            }
+            match tty.read(&mut buf) {
+                Ok(n) if n > 0 => answer.extend_from_slice(&buf[..n]),
+                _ => break,
+            }
+            // Terminals answer in order, so the size reports come before the device attributes
+            if has_device_attributes(&answer) {
+                break;
+            }
+        }
+    }
+    unsafe {
+        libc::tcflush(fd, libc::TCIFLUSH);
+        libc::tcsetattr(fd, libc::TCSANOW, &original);
+    }
+    Some(parse_size_reports(&answer))
+}
@@ -1,11 +1,39 @@
-use std::{env::current_dir, fs};
+use std::{collections::HashSet, env::current_dir, fs};
 
-use image::ImageReader;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{ImageReader, Rgba, RgbaImage};
 
 use crate::{
-    cell::{self, CellGrid},
-    color::Color,
-    outputs::AsciiImageRenderer,
+    ansi::{self, SgrEmitter},
+    batch,
+    cache::{self, Cache},
+    cell::{self, CellGrid, ComputedCell, ComputedCellGrid, CELL_H, CELL_W},
+    charsets,
+    color::{Color, RGBColorU8},
+    config::{ConfigFile, Settings},
+    fetch::{self, FetchSettings},
+    fit::{self, Fit, FitMode, Gravity},
+    grid_file::{self, GridFormat},
+    import::{self, ImportWarnings},
+    layout::{self, Align, BorderStyle, Layout, Spacing, VerticalAlign},
+    limits::{self, DecodeLimits, LimitError},
+    outputs::{
+        self,
+        ans::{self, AnsImageRenderer, Sauce, SauceMetadata},
+        bitmap_font::{self, GLYPH_H, GLYPH_W},
+        codegen::{self, CodeGenerator, CodegenLanguage, CodegenSettings},
+        emoji::{self, EmojiImageRenderer},
+        irc::{self, IrcImageRenderer},
+        kitty::{self, KittyFormat, KittyImageRenderer},
+        png::PngImageRenderer,
+        sixel::{self, SixelImageRenderer},
+        svg::{SvgImageRenderer, SvgSettings},
+        AsciiImageRenderer, CaptureWriter, MultiWriter,
+    },
+    passthrough::{self, Passthrough, PassthroughWriter},
+    terminal::{self, SizeReports},
+    transform::{self, Crop, CropValue, Flip, Transform, Trim},
+    utils,
 };
 
 #[test]
@@ -24,7 +52,7 @@ fn generate_cell_test() {
 
     use std::time::Instant;
     let now = Instant::now();
-    let mut cells = CellGrid::from(&img.clone().into());
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
     let elapsed = now.elapsed();
     println!(
         "Image size ({}x{}) | Cells count: {} | Time taken: {:.2?}",
@@ -48,7 +76,7 @@ fn round_cell_test() {
 
     let (mut cells, cell_generation_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
         (cells, now.elapsed())
     };
 
@@ -56,7 +84,7 @@ fn round_cell_test() {
     cell::round_cells(&mut cells.cells);
     let round_cell_time = before_round.elapsed();
 
-    fs::create_dir("./test-outputs/");
+    let _ = fs::create_dir("./test-outputs/");
     cells.save_as("./test-outputs/rounded_cells4.png").unwrap();
 
     println!("Image size ({}x{}) | Cells count: {} | Cell Generate Time: {:.2?} | Round Cell Pixels time: {:.2?}", img.width(), img.height(), cells.len(), cell_generation_time, round_cell_time);
@@ -75,7 +103,7 @@ fn round_cell_bw_test() {
 
     let (mut cells, cell_generation_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
         (cells, now.elapsed())
     };
 
@@ -85,7 +113,7 @@ fn round_cell_bw_test() {
     cell::round_cells_with_ab(&mut cells.cells, &Color::WHITE, &Color::TRANSPARENT);
     let round_cell_time = before_round.elapsed();
 
-    fs::create_dir("./test-outputs/");
+    let _ = fs::create_dir("./test-outputs/");
     cells
         .save_as("./test-outputs/bw_rounded_cells4.png")
         .unwrap();
@@ -106,7 +134,7 @@ fn print_bw_test() {
 
     let (mut cells, cell_generation_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
         (cells, now.elapsed())
     };
     // Transparent is used instead of black for bw as the alpha channel is included as part of the comparisons Hence using transparency gives better results
@@ -122,7 +150,7 @@ fn print_bw_test() {
         (img, now.elapsed())
     };
 
-    fs::create_dir("./test-outputs/");
+    let _ = fs::create_dir("./test-outputs/");
     cell::round_cells_with_ab(&mut cells.cells, &Color::WHITE, &Color::TRANSPARENT);
     cells
         .save_as("./test-outputs/bw_print_rounded_cells.png")
@@ -146,7 +174,7 @@ fn print_colored_test() {
 
     let (mut cells, cell_generation_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
         (cells, now.elapsed())
     };
 
@@ -163,7 +191,7 @@ fn print_colored_test() {
         (img, now.elapsed())
     };
 
-    fs::create_dir("./test-outputs/");
+    let _ = fs::create_dir("./test-outputs/");
     cells
         .save_as("./test-outputs/print_colored_cells.png")
         .unwrap();
@@ -201,7 +229,7 @@ fn large_colored_test() {
 
     let (mut cells, cell_generation_time) = {
         let now = Instant::now();
-        let cells = CellGrid::from(&img.clone().into());
+        let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
         (cells, now.elapsed())
     };
 
@@ -218,7 +246,7 @@ fn large_colored_test() {
         (img, now.elapsed())
     };
 
-    fs::create_dir("./test-outputs/");
+    let _ = fs::create_dir("./test-outputs/");
     cells
         .save_as("./test-outputs/print_colored_cells.png")
         .unwrap();
@@ -242,3 +270,1625 @@ fn large_colored_test() {
         cell_generation_time, round_cell_time, string_time
     );
 }
+
+#[test]
+fn svg_output_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+    let (s, _) = SvgImageRenderer::render(&computed, true, None, 0.25, &SvgSettings::default());
+
+    let _ = fs::create_dir("./test-outputs/");
+    fs::write("./test-outputs/print_colored_cells.svg", &s).unwrap();
+
+    assert!(s.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(s.trim_end().ends_with("</svg>"));
+    // One text element per row of cells
+    assert_eq!(s.matches("<text ").count(), computed.height());
+    assert!(s.contains("<rect "));
+}
+
+#[test]
+fn bitmap_font_covers_charsets_test() {
+    for c in charsets::BRAILLE.chars().chain(charsets::CLASSIC.chars()) {
+        assert!(bitmap_font::glyph(c).is_some(), "Missing glyph for {:?}", c);
+    }
+    let full = bitmap_font::glyph('█').unwrap();
+    let upper_half = bitmap_font::glyph('▀').unwrap();
+    for y in 0..GLYPH_H {
+        for x in 0..GLYPH_W {
+            assert!(bitmap_font::is_set(&full, x, y));
+            assert_eq!(bitmap_font::is_set(&upper_half, x, y), y < GLYPH_H / 2);
+        }
+    }
+    // Braille dots should not touch each other
+    let all_dots = bitmap_font::glyph('⣿').unwrap();
+    assert!(!bitmap_font::is_set(&all_dots, 0, 0));
+    assert!(!bitmap_font::is_set(&all_dots, 3, 1));
+}
+
+#[test]
+fn png_output_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+    let (png, _) = PngImageRenderer::render(&computed, true, None, 0.25, 2);
+
+    assert_eq!(png.width(), computed.width() as u32 * GLYPH_W * 2);
+    assert_eq!(png.height(), computed.height() as u32 * GLYPH_H * 2);
+
+    let _ = fs::create_dir("./test-outputs/");
+    png.save("./test-outputs/print_colored_cells_preview.png")
+        .unwrap();
+}
+
+#[test]
+fn sgr_emitter_test() {
+    let red = RGBColorU8 { r: 255, g: 0, b: 0 };
+    let blue = RGBColorU8 { r: 0, g: 0, b: 255 };
+    let mut sgr = SgrEmitter::new();
+    let mut s = String::new();
+
+    sgr.set(&mut s, Some(red), Some(blue)).unwrap();
+    assert_eq!(s, "\x1b[38;2;255;0;0;48;2;0;0;255m");
+    s.clear();
+
+    // Nothing changed, nothing should be emitted
+    sgr.set(&mut s, Some(red), Some(blue)).unwrap();
+    assert_eq!(s, "");
+
+    sgr.set(&mut s, Some(blue), Some(blue)).unwrap();
+    assert_eq!(s, "\x1b[38;2;0;0;255m");
+    s.clear();
+
+    sgr.set_back(&mut s, None).unwrap();
+    assert_eq!(s, "\x1b[49m");
+    s.clear();
+
+    sgr.reset(&mut s).unwrap();
+    sgr.reset(&mut s).unwrap();
+    assert_eq!(s, ansi::RESET);
+}
+
+#[test]
+fn minimal_sgr_output_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+    let (colored, _) = AsciiImageRenderer::render(&computed, true, None, 0.25);
+    let (plain, _) = AsciiImageRenderer::render(&computed, false, None, 0.25);
+
+    assert_eq!(colored.lines().count(), plain.lines().count());
+    for line in colored.lines() {
+        // Colors never bleed into the next line
+        assert!(!line.contains('\x1b') || line.ends_with(ansi::RESET));
+        // At most one sequence to change colors per cell, plus the reset
+        assert!(line.matches('\x1b').count() <= computed.width() + 1);
+    }
+
+    let rgb = |r, g, b| Color { r, g, b, a: 1.0 };
+    let (red, green, blue) = (rgb(1.0, 0.0, 0.0), rgb(0.0, 1.0, 0.0), rgb(0.0, 0.0, 1.0));
+    let cell = |fore, back| ComputedCell {
+        fore,
+        back,
+        bitmask: 0b0000_0001,
+    };
+    // A run of the same colors, a cell whose fore & back are the same, and a change of fore color only
+    let grid = ComputedCellGrid::new(
+        vec![
+            cell(red, blue),
+            cell(red, blue),
+            cell(red, red),
+            cell(red, blue),
+            cell(green, blue),
+            cell(red, blue),
+        ],
+        3,
+        2,
+    )
+    .unwrap();
+    let (s, _) = AsciiImageRenderer::render(&grid, true, None, 0.25);
+    let c = outputs::cell_char(&charsets::BRAILLE.chars().collect::<Vec<_>>(), 1).unwrap();
+    assert_eq!(
+        s,
+        format!(
+            "\x1b[38;2;255;0;0;48;2;0;0;255m{c}{c}\x1b[48;2;255;0;0m \x1b[0m\n\
+             \x1b[38;2;255;0;0;48;2;0;0;255m{c}\x1b[38;2;0;255;0m{c}\x1b[38;2;255;0;0m{c}\x1b[0m\n"
+        )
+    );
+
+    // Smaller than setting both colors & resetting them around every cell
+    let per_cell: usize = grid
+        .cells
+        .iter()
+        .map(|cell| {
+            let (fore, back): (RGBColorU8, RGBColorU8) = (cell.fore.into(), cell.back.into());
+            format!(
+                "\x1b[38;2;{};{};{};48;2;{};{};{}m{}{}",
+                fore.r,
+                fore.g,
+                fore.b,
+                back.r,
+                back.g,
+                back.b,
+                c,
+                ansi::RESET
+            )
+            .len()
+        })
+        .sum::<usize>()
+        + grid.height();
+    assert!(s.len() < per_cell);
+}
+
+#[test]
+fn streaming_render_test() {
+    /// Records every write, to check that rows are written one at a time
+    struct RecordingWriter(Vec<Vec<u8>>);
+    impl std::io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+
+    let mut writer = RecordingWriter(Vec::new());
+    AsciiImageRenderer::render_to(&mut writer, &computed, true, None, 0.25).unwrap();
+    let (s, _) = AsciiImageRenderer::render(&computed, true, None, 0.25);
+
+    assert_eq!(writer.0.len(), computed.height());
+    assert_eq!(writer.0.concat(), s.into_bytes());
+}
+
+#[test]
+fn grid_file_round_trip_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+
+    for format in [GridFormat::Json, GridFormat::Binary] {
+        let mut bytes = Vec::new();
+        grid_file::write_grid(&mut bytes, &computed, format).unwrap();
+        let loaded = grid_file::read_grid(&mut bytes.as_slice()).unwrap();
+
+        if format == GridFormat::Json {
+            assert_eq!(loaded, computed, "Json grid changed after loading");
+        } else {
+            // Binary grids are rounded to 8 bits per channel
+            assert_eq!(loaded.cells.len(), computed.cells.len());
+            for (loaded, cell) in loaded.cells.iter().zip(&computed.cells) {
+                assert_eq!(loaded.bitmask, cell.bitmask);
+                for (a, b) in [(loaded.fore, cell.fore), (loaded.back, cell.back)] {
+                    for (x, y) in [(a.r, b.r), (a.g, b.g), (a.b, b.b), (a.a, b.a)] {
+                        assert!((x - y).abs() <= 0.5 / 255.0 + f32::EPSILON);
+                    }
+                }
+            }
+        }
+        // Colors of binary grids can be off by one after rounding, so only their characters are compared
+        let colored = format == GridFormat::Json;
+        let (rendered, _) = AsciiImageRenderer::render(&loaded, colored, None, 0.25);
+        let (expected, _) = AsciiImageRenderer::render(&computed, colored, None, 0.25);
+        assert_eq!(rendered, expected, "{:?} grid renders differently", format);
+        if format == GridFormat::Binary {
+            // Header of magic, version, width & height, then 9 bytes per cell
+            assert_eq!(bytes.len(), 8 + 1 + 8 + computed.cells.len() * 9);
+        }
+    }
+
+    // Binary grids keep 8 bits per channel
+    let cell = ComputedCell {
+        fore: Color {
+            r: 0.5,
+            g: 0.25,
+            b: 1.0,
+            a: 1.0,
+        },
+        back: Color::TRANSPARENT,
+        bitmask: 0b1010_0101,
+    };
+    let grid = ComputedCellGrid::new(vec![cell], 1, 1).unwrap();
+    let mut bytes = Vec::new();
+    grid_file::write_grid(&mut bytes, &grid, GridFormat::Binary).unwrap();
+    assert_eq!(&bytes[17..], &[128, 64, 255, 255, 0, 0, 0, 0, 0b1010_0101]);
+    let loaded = grid_file::read_grid(&mut bytes.as_slice()).unwrap();
+    assert_eq!(loaded.cells[0].bitmask, 0b1010_0101);
+    assert_eq!(loaded.cells[0].fore.r, 128.0 / 255.0);
+    // Truncated cells are rejected
+    assert!(grid_file::read_grid(&mut &bytes[..bytes.len() - 1]).is_err());
+
+    // Grids whose size does not match their cells are rejected
+    let json =
+        r#"{"cells":[{"fore":[0,0,0,1],"back":[1,1,1,1],"bitmask":3}],"width":2,"height":1}"#;
+    assert!(grid_file::read_grid(&mut json.as_bytes()).is_err());
+}
+
+#[test]
+fn char_index_bitmask_inverse_test() {
+    for bitmask in 0..=255u8 {
+        let index = charsets::cell_bitmask_to_char_index(bitmask);
+        assert_eq!(charsets::char_index_to_cell_bitmask(index), bitmask);
+    }
+}
+
+#[test]
+fn import_art_round_trip_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+
+    for charset in [charsets::BRAILLE, charsets::CLASSIC] {
+        for colored in [true, false] {
+            let (art, _) = AsciiImageRenderer::render(&computed, colored, Some(charset), 0.25);
+            let (imported, warnings) = import::import_art(&art);
+            assert_eq!(warnings, ImportWarnings::None);
+            assert_eq!(imported.width(), computed.width());
+            assert_eq!(imported.height(), computed.height());
+
+            let (rendered, _) = AsciiImageRenderer::render(&imported, colored, Some(charset), 0.25);
+            assert_eq!(rendered, art);
+        }
+    }
+
+    let _ = fs::create_dir("./test-outputs/");
+    let (art, _) = AsciiImageRenderer::render(&computed, true, None, 0.25);
+    import::import_art(&art)
+        .0
+        .to_cell_grid()
+        .save_as("./test-outputs/imported_cells.png")
+        .unwrap();
+}
+
+#[test]
+fn import_art_sgr_test() {
+    let (grid, warnings) =
+        import::import_art("\x1b[31;44m\u{2588}\x1b[0m\u{2584}x\n\x1b[38;5;21m\u{28ff}\r\n");
+    assert_eq!(warnings, ImportWarnings::UnknownCharacters(1));
+    assert_eq!((grid.width(), grid.height()), (3, 2));
+
+    let full = &grid.cells[0];
+    assert_eq!(full.bitmask, 0xff);
+    assert_eq!(
+        RGBColorU8::from(full.fore),
+        RGBColorU8 { r: 205, g: 0, b: 0 }
+    );
+    assert_eq!(
+        RGBColorU8::from(full.back),
+        RGBColorU8 { r: 0, g: 0, b: 238 }
+    );
+
+    // The lower half block is not part of any charset, its bitmask is sampled from the bitmap font
+    let lower_half = &grid.cells[1];
+    assert_eq!(lower_half.bitmask, 0xf0);
+    assert_eq!(lower_half.fore, import::DEFAULT_FORE);
+    assert_eq!(lower_half.back, import::DEFAULT_BACK);
+
+    assert_eq!(grid.cells[3].bitmask, 0xff);
+    assert_eq!(
+        RGBColorU8::from(grid.cells[3].fore),
+        RGBColorU8 { r: 0, g: 0, b: 255 }
+    );
+    // Short lines are padded with empty cells
+    assert_eq!(grid.cells[5].bitmask, 0);
+}
+
+#[test]
+fn import_art_osc_test() {
+    // Hyperlinks & window titles are skipped, whether they end with BEL or ST
+    let (grid, warnings) = import::import_art(
+        "\x1b]0;title\x07\x1b]8;;https://example.com\x1b\\\u{28ff}\x1b]8;;\x1b\\\u{2801}\n",
+    );
+    assert_eq!(warnings, ImportWarnings::None);
+    assert_eq!((grid.width(), grid.height()), (2, 1));
+    assert_eq!(grid.cells[0].bitmask, 0xff);
+}
+
+#[test]
+fn ans_output_sauce_round_trip_test() {
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+
+    let cells = CellGrid::try_from(&img.to_rgba32f()).unwrap();
+    let computed = cells.compute(false);
+    let metadata = SauceMetadata {
+        title: "Test image".to_string(),
+        author: "clir_rs".to_string(),
+        group: "A group name that is longer than twenty characters".to_string(),
+        ..Default::default()
+    };
+    let bytes = AnsImageRenderer::render(&computed, true, 0.25, &metadata, "20240229");
+
+    let _ = fs::create_dir("./test-outputs/");
+    fs::write("./test-outputs/print_colored_cells.ans", &bytes).unwrap();
+
+    let sauce = Sauce::parse(&bytes).unwrap();
+    assert_eq!(sauce.metadata.title, "Test image");
+    assert_eq!(sauce.metadata.author, "clir_rs");
+    assert_eq!(sauce.metadata.group, "A group name that is");
+    assert_eq!(sauce.metadata.font, "IBM VGA");
+    assert_eq!(sauce.date, "20240229");
+    assert_eq!(ans::date_from_days(19782), "20240229");
+    assert_eq!(ans::date_from_days(0), "19700101");
+    assert_eq!(sauce.data_type, Sauce::DATA_TYPE_CHARACTER);
+    assert_eq!(sauce.file_type, Sauce::FILE_TYPE_ANSI);
+    assert_eq!(sauce.width as usize, computed.width());
+    assert_eq!(sauce.lines as usize, computed.height());
+    assert_eq!(sauce.to_bytes().len(), ans::SAUCE_LEN);
+
+    // The art is followed by the EOF character, then the record
+    let art = &bytes[..sauce.file_size as usize];
+    assert_eq!(bytes[art.len()], ans::EOF_CHAR);
+    assert_eq!(bytes.len(), art.len() + 1 + ans::SAUCE_LEN);
+    assert_eq!(art.split(|b| *b == b'\n').count() - 1, computed.height());
+    // Only ascii escape sequences & CP437 blocks are used
+    assert!(art
+        .iter()
+        .all(|b| b.is_ascii() || [0xb0, 0xb1, 0xb2, 0xdb, 0xdc, 0xdd, 0xde, 0xdf].contains(b)));
+}
+
+#[test]
+fn ans_output_blocks_test() {
+    let red = Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    let cell = |bitmask: u8| ComputedCell {
+        fore: Color::WHITE,
+        back: red,
+        bitmask,
+    };
+    let grid = ComputedCellGrid::new(vec![cell(0xff), cell(0x0f), cell(0x55)], 3, 1).unwrap();
+    let bytes = AnsImageRenderer::render(&grid, true, 0.25, &SauceMetadata::default(), "20240229");
+    let art = &bytes[..Sauce::parse(&bytes).unwrap().file_size as usize];
+    // White is bright, so it is drawn as bold
+    assert_eq!(art, b"\x1b[0;1;37;41m\xdb\xdf\xdd\r\n\x1b[0m");
+
+    // Bright backgrounds are not available, so the colors are swapped & the upper half becomes a lower half
+    let inverted = ComputedCellGrid::new(
+        vec![ComputedCell {
+            fore: red,
+            back: Color::WHITE,
+            bitmask: 0x0f,
+        }],
+        1,
+        1,
+    )
+    .unwrap();
+    let bytes =
+        AnsImageRenderer::render(&inverted, true, 0.25, &SauceMetadata::default(), "20240229");
+    assert!(bytes.starts_with(b"\x1b[0;1;37;41m\xdc"));
+}
+
+#[test]
+fn irc_output_test() {
+    let red = Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    let blue = Color {
+        r: 0.0,
+        g: 0.0,
+        b: 1.0,
+        a: 1.0,
+    };
+    assert_eq!(irc::nearest_color(red.into()), 4);
+    assert_eq!(irc::nearest_color(Color::BLACK.into()), 1);
+
+    let cell = |fore: Color, back: Color, bitmask: u8| ComputedCell {
+        fore,
+        back,
+        bitmask,
+    };
+    let grid = ComputedCellGrid::new(
+        vec![
+            cell(red, blue, 0xff),
+            cell(red, blue, 0x0f),
+            cell(Color::WHITE, blue, 0x0f),
+            cell(red, Color::TRANSPARENT, 0x0f),
+            // Same fore & back, drawn as a space
+            cell(blue, blue, 0x0f),
+        ],
+        5,
+        1,
+    )
+    .unwrap();
+
+    let (s, _, truncated) = IrcImageRenderer::render(&grid, true, None, 0.25, None);
+    assert_eq!(
+        s,
+        "\x0304,60\u{28ff}\u{281b}\x0300\u{281b}\x03\x0304\u{281b}\x0304,60 \n"
+    );
+    assert_eq!(truncated, 0);
+
+    // Colors are only emitted when they change, so each line fits more cells than a naive estimate
+    let (s, _, truncated) = IrcImageRenderer::render(&grid, true, None, 0.25, Some(16));
+    assert_eq!(s, "\x0304,60\u{28ff}\u{281b}\n");
+    assert_eq!(truncated, 1);
+    assert!(s.trim_end().len() <= 16);
+}
+
+#[test]
+fn emoji_output_test() {
+    let red = Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    let blue = Color {
+        r: 0.0,
+        g: 0.3,
+        b: 1.0,
+        a: 1.0,
+    };
+    assert_eq!(emoji::nearest_square(red.into()), '\u{1f7e5}');
+    assert_eq!(emoji::nearest_square(Color::BLACK.into()), emoji::BLACK);
+    // Grays should not be mistaken for a color
+    let gray = RGBColorU8 {
+        r: 180,
+        g: 180,
+        b: 180,
+    };
+    assert_eq!(emoji::nearest_square(gray), emoji::WHITE);
+
+    let cell = |fore: Color, back: Color, bitmask: u8| ComputedCell {
+        fore,
+        back,
+        bitmask,
+    };
+    let grid = ComputedCellGrid::new(
+        vec![
+            // Top half red, bottom half blue
+            cell(red, blue, 0x0f),
+            // Top half blue, bottom half red
+            cell(red, blue, 0xf0),
+            cell(red, Color::TRANSPARENT, 0x00),
+        ],
+        3,
+        1,
+    )
+    .unwrap();
+
+    let s = EmojiImageRenderer::render(&grid, true, 0.5);
+    assert_eq!(
+        s,
+        "\u{1f7e5}\u{1f7e6}\u{2b1c}\n\u{1f7e6}\u{1f7e5}\u{2b1c}\n"
+    );
+
+    let s = EmojiImageRenderer::render(&grid, false, 0.5);
+    assert_eq!(s, "\u{2b1b}\u{2b1c}\u{2b1c}\n\u{2b1c}\u{2b1b}\u{2b1c}\n");
+}
+
+#[test]
+fn emoji_output_width_one_test() {
+    // -w 1 with emoji is at least one cell wide, which is CELL_W pixels of the resized image
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap()
+        .resize_exact(
+            CELL_W as u32,
+            3 * CELL_H as u32,
+            image::imageops::FilterType::Triangle,
+        );
+    let computed = CellGrid::try_from(&img.to_rgba32f())
+        .unwrap()
+        .compute(false);
+    assert_eq!((computed.width(), computed.height()), (1, 3));
+    let s = EmojiImageRenderer::render(&computed, true, 0.5);
+    assert_eq!(s.lines().count(), 6);
+    assert!(s.lines().all(|line| line.chars().count() == 1));
+
+    // Images smaller than a cell are an error rather than a grid without columns
+    let narrow = RgbaImage::new(CELL_W as u32 - 1, CELL_H as u32);
+    assert!(CellGrid::try_from(&image::DynamicImage::ImageRgba8(narrow).to_rgba32f()).is_err());
+}
+
+#[test]
+fn codegen_escape_test() {
+    let text = "\x1b[31m\u{28ff}\"\\?%'\x1b[0m";
+    assert_eq!(
+        codegen::escape_rust(text),
+        "\\x1b[31m\u{28ff}\\\"\\\\?%'\\x1b[0m"
+    );
+    assert_eq!(
+        codegen::escape_c(text),
+        "\\033[31m\\342\\243\\277\\\"\\\\\\?%'\\033[0m"
+    );
+    assert_eq!(
+        codegen::escape_printf(text),
+        "\\033[31m\u{28ff}\"\\\\?%%'\\''\\033[0m"
+    );
+    assert_eq!(codegen::identifier("2nd-logo"), "_2nd_logo");
+}
+
+#[test]
+fn codegen_output_test() {
+    let art = "\x1b[31mab\x1b[0m\n cd\n";
+    let settings = CodegenSettings::default();
+    assert_eq!(
+        CodeGenerator::generate(art, CodegenLanguage::Rust, &settings),
+        "pub const ART: &str = concat!(\n    \"\\x1b[31mab\\x1b[0m\\n\",\n    \" cd\\n\",\n);\n"
+    );
+    assert_eq!(
+        CodeGenerator::generate(art, CodegenLanguage::Shell, &settings),
+        "#!/bin/sh\nprintf '\\033[31mab\\033[0m\\n'\nprintf ' cd\\n'\n"
+    );
+
+    let settings = CodegenSettings {
+        name: "logo".to_string(),
+        lines: true,
+    };
+    assert_eq!(
+        CodeGenerator::generate(art, CodegenLanguage::C, &settings),
+        "#ifndef LOGO_H\n#define LOGO_H\n\n#define logo_LINES 2\n\n\
+         static const char *const logo[] = {\n    \"\\033[31mab\\033[0m\",\n    \" cd\",\n};\n\n#endif /* LOGO_H */\n"
+    );
+}
+
+#[test]
+fn sixel_output_test() {
+    let red = Rgba([255, 0, 0, 255]);
+    let blue = Rgba([0, 0, 255, 255]);
+    let mut img = RgbaImage::from_pixel(4, 2, red);
+    img.put_pixel(1, 1, blue);
+    img.put_pixel(2, 1, blue);
+    img.put_pixel(3, 1, Rgba([0, 255, 0, 0]));
+
+    assert_eq!(
+        SixelImageRenderer::render(&img, 0.5),
+        "\x1bP0;1;0q\"1;1;4;2#0;2;100;0;0#1;2;0;0;100#0B@@@$#1?AA\x1b\\"
+    );
+
+    // Runs of more than 3 characters are compressed, and each band of 6 rows is separated by '-'
+    let img = RgbaImage::from_pixel(6, 7, red);
+    assert_eq!(
+        SixelImageRenderer::render(&img, 0.5),
+        "\x1bP0;1;0q\"1;1;6;7#0;2;100;0;0#0!6~-#0!6@\x1b\\"
+    );
+
+    let colors = [
+        ([0, 0, 0], 1),
+        ([10, 0, 0], 1),
+        ([200, 0, 0], 1),
+        ([210, 0, 0], 1),
+    ];
+    assert_eq!(sixel::quantize(&colors, 2), vec![[5, 0, 0], [205, 0, 0]]);
+    assert_eq!(sixel::quantize(&colors, 8).len(), 4);
+}
+
+#[test]
+fn sixel_output_fit_test() {
+    // A wide image of red, green & blue thirds, shown in a box of 2x1 cells
+    let colors = [
+        Rgba([255, 0, 0, 255]),
+        Rgba([0, 255, 0, 255]),
+        Rgba([0, 0, 255, 255]),
+    ];
+    let img = RgbaImage::from_fn(48, 8, |x, _| colors[x as usize / 16]);
+    let img = image::DynamicImage::ImageRgba8(img);
+    let (width, height) = outputs::graphics_size(2, 1, None);
+    // Cells are assumed to be the size of the bitmap font, unless their size was detected
+    assert_eq!((width, height), (16, 16));
+    assert_eq!(outputs::graphics_size(2, 1, Some((9.0, 20.0))), (18, 20));
+    let fit = |mode| Fit {
+        mode,
+        gravity: Gravity::CENTER,
+        background: Color::TRANSPARENT,
+        filter: image::imageops::FilterType::Nearest,
+    };
+
+    // Cover keeps the middle of the image, like the cells
+    let covered = fit(FitMode::Cover)
+        .apply(&img, width, height, 1.0)
+        .into_rgba8();
+    assert_eq!((covered.width(), covered.height()), (width, height));
+    assert_eq!(
+        SixelImageRenderer::render(&covered, 0.5),
+        "\x1bP0;1;0q\"1;1;16;16#0;2;0;100;0#0!16~-#0!16~-#0!16N\x1b\\"
+    );
+
+    // Contain pads the top & bottom, which are left transparent
+    let contained = fit(FitMode::Contain)
+        .apply(&img, width, height, 1.0)
+        .into_rgba8();
+    assert_eq!(contained.get_pixel(0, 0)[3], 0);
+    assert_eq!(contained.get_pixel(0, height / 2), &colors[0]);
+    assert_eq!(contained.get_pixel(width - 1, height / 2), &colors[2]);
+}
+
+#[test]
+fn kitty_output_test() {
+    assert_eq!(
+        KittyImageRenderer::transmit("a=T,f=100", b"hi"),
+        "\x1b_Ga=T,f=100,m=0;aGk=\x1b\\"
+    );
+
+    let img = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255]));
+    assert_eq!(
+        KittyImageRenderer::render(&img, KittyFormat::Rgba, 3, 2).unwrap(),
+        "\x1b_Ga=T,f=32,c=3,r=2,q=2,s=1,v=1,m=0;/wAA/w==\x1b\\"
+    );
+
+    // Large payloads are split into chunks, with m=1 on all but the last
+    let data = vec![0u8; 4000];
+    let s = KittyImageRenderer::transmit("a=T,f=32", &data);
+    let sequences: Vec<&str> = s.split_terminator("\x1b\\").collect();
+    assert_eq!(sequences.len(), 2);
+    assert!(sequences[0].starts_with("\x1b_Ga=T,f=32,m=1;"));
+    assert!(sequences[1].starts_with("\x1b_Gm=0;"));
+    for sequence in sequences {
+        let payload = sequence.split_once(';').unwrap().1;
+        assert!(payload.len() <= kitty::CHUNK_SIZE);
+    }
+}
+
+#[test]
+fn passthrough_test() {
+    use std::io::Write;
+
+    assert_eq!(
+        passthrough::wrap(b"\x1b[31m", Passthrough::Tmux),
+        b"\x1bPtmux;\x1b\x1b[31m\x1b\\"
+    );
+    // Every ESC inside the sequence is doubled, including the one of its string terminator
+    assert_eq!(
+        passthrough::wrap(b"\x1b_Gm=0;AAAA\x1b\\", Passthrough::Tmux),
+        b"\x1bPtmux;\x1b\x1b_Gm=0;AAAA\x1b\x1b\\\x1b\\"
+    );
+    assert_eq!(
+        passthrough::wrap(b"\x1bPq#0!6~\x1b\\", Passthrough::Screen),
+        b"\x1bP\x1bPq#0!6~\x1b\x1b\\\x1bP\\\x1b\\"
+    );
+    let long = [b"\x1b_G".as_slice(), &[b'A'; 1000]].concat();
+    let wrapped = passthrough::wrap(&long, Passthrough::Screen);
+    assert_eq!(wrapped.len(), long.len() + 2 * 4);
+    assert!(wrapped.starts_with(b"\x1bP\x1b_GAAA"));
+
+    // Text is written as is, and sequences split across writes are kept until they are complete
+    let mut writer = PassthroughWriter::new(Vec::new(), Passthrough::Tmux);
+    writer.write_all(b"a\x1b[38;2;").unwrap();
+    writer.write_all(b"1;2;3mb\x1b[0m\n").unwrap();
+    assert_eq!(
+        writer.into_inner(),
+        b"a\x1bPtmux;\x1b\x1b[38;2;1;2;3m\x1b\\b\x1bPtmux;\x1b\x1b[0m\x1b\\\n"
+    );
+
+    let mut writer = PassthroughWriter::new(Vec::new(), Passthrough::None);
+    writer.write_all(b"a\x1b[0m").unwrap();
+    assert_eq!(writer.into_inner(), b"a\x1b[0m");
+}
+
+#[test]
+fn decode_image_test() {
+    let bytes = fs::read("./test_resource/test_image.png").unwrap();
+    let img = utils::decode_image(&bytes).unwrap();
+    assert!(img.width() > 0 && img.height() > 0);
+
+    assert!(utils::decode_image(&[]).is_err());
+    assert!(utils::decode_image(b"hello world").is_err());
+}
+
+#[test]
+fn batch_sources_test() {
+    let expand = |sources: &[&str]| -> Vec<Result<String, String>> {
+        let sources: Vec<String> = sources.iter().map(|x| x.to_string()).collect();
+        batch::expand_sources(&sources, false, true)
+            .into_iter()
+            .map(|x| x.map_err(|e| e.to_string()))
+            .collect()
+    };
+
+    assert_eq!(
+        expand(&["./test_resource"]),
+        vec![
+            Ok("./test_resource/test_image.png".to_string()),
+            Ok("./test_resource/test_image_2.png".to_string())
+        ]
+    );
+    assert_eq!(
+        expand(&["test_resource/*_2.png", "-", "missing.png"]),
+        vec![
+            Ok("test_resource/test_image_2.png".to_string()),
+            Ok("-".to_string()),
+            Ok("missing.png".to_string())
+        ]
+    );
+    assert_eq!(
+        expand(&["./test_resource/*.jpg"]),
+        vec![Err("No files matched './test_resource/*.jpg'".to_string())]
+    );
+}
+
+#[test]
+fn batch_output_name_test() {
+    assert_eq!(batch::source_stem("icons/logo.png"), "logo");
+    assert_eq!(
+        batch::source_stem("https://example.com/a/cat.jpg?size=2"),
+        "cat"
+    );
+    assert_eq!(batch::source_stem("-"), "stdin");
+
+    assert_eq!(
+        batch::output_name(batch::DEFAULT_TEMPLATE, "icons/logo.png", 1, "ans").unwrap(),
+        "logo.ans"
+    );
+    assert_eq!(
+        batch::output_name("{index}_{stem}.txt", "icons/logo.png", 12, "ans").unwrap(),
+        "12_logo.txt"
+    );
+    assert!(batch::output_name("{name}.txt", "logo.png", 1, "ans").is_err());
+    assert!(batch::output_name("out/{stem}.txt", "logo.png", 1, "ans").is_err());
+}
+
+#[test]
+fn move_stdin_source_test() {
+    let options = batch::value_options(
+        "Usage: clir_rs [--output <output>] [-w <width>] [-t <transparency-t>] [--trim] [--header <header...>] [--] [<source...>]",
+    );
+    let expected: HashSet<String> = [
+        "--output",
+        "-w",
+        "--width",
+        "-t",
+        "--transparency-t",
+        "--header",
+    ]
+    .map(String::from)
+    .into();
+    assert_eq!(options, expected);
+
+    let moved = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        batch::move_stdin_source(&args, &options)
+    };
+    assert_eq!(moved(&["-"]), ["--", "-"]);
+    // The value of an option is kept, even when it is `-`
+    assert_eq!(moved(&["-w", "-"]), ["-w", "-"]);
+    assert_eq!(moved(&["-", "-w", "5"]), ["-w", "5", "--", "-"]);
+    assert_eq!(moved(&["--", "-"]), ["--", "-"]);
+    assert_eq!(moved(&["--output", "-", "-"]), ["--output", "-", "--", "-"]);
+    assert_eq!(moved(&["--trim", "-"]), ["--trim", "--", "-"]);
+    assert_eq!(
+        moved(&["--trim", "-", "--", "a.png"]),
+        ["--trim", "--", "-", "a.png"]
+    );
+    assert_eq!(moved(&["a.png", "--trim"]), ["a.png", "--trim"]);
+}
+
+#[test]
+fn config_presets_test() {
+    let file = ConfigFile::parse(
+        r#"
+charset = "braille"
+width = 80
+
+[presets.logo]
+width = 40
+transparency-t = 0.5
+no-color = true
+"#,
+    )
+    .unwrap();
+    assert_eq!(file.defaults.charset.as_deref(), Some("braille"));
+    assert_eq!(file.settings(None).unwrap().width, Some(80));
+
+    let logo = file.settings(Some("logo")).unwrap();
+    assert_eq!(logo.width, Some(40));
+    assert_eq!(logo.charset.as_deref(), Some("braille"));
+    assert_eq!(logo.transparency_t, Some(0.5));
+    assert_eq!(logo.no_color, Some(true));
+
+    let err = file.settings(Some("banner")).unwrap_err().to_string();
+    assert!(err.contains("banner") && err.contains("logo"));
+    assert!(ConfigFile::parse("widht = 10").is_err());
+    assert!(ConfigFile::parse("width = \"wide\"").is_err());
+
+    // Environment variables take precedence over the preset
+    let env = Settings::from_env_vars([
+        ("CLIR_WIDTH".to_string(), "60".to_string()),
+        ("CLIR_CHARSET".to_string(), "classic".to_string()),
+        ("CLIR_FORMAT".to_string(), "svg".to_string()),
+        ("CLIR_TRIM".to_string(), "true".to_string()),
+        ("HOME".to_string(), "/root".to_string()),
+    ])
+    .unwrap();
+    let merged = logo.merge(env);
+    assert_eq!(merged.width, Some(60));
+    assert_eq!(merged.charset.as_deref(), Some("classic"));
+    assert_eq!(merged.format.as_deref(), Some("svg"));
+    assert_eq!(merged.trim, Some(true));
+    assert_eq!(merged.transparency_t, Some(0.5));
+    assert!(Settings::from_env_vars([("CLIR_WIDTH".to_string(), "wide".to_string())]).is_err());
+    assert!(
+        Settings::from_env_vars([("CLIR_WIDTH".to_string(), "1\nfoo = 2".to_string())]).is_err()
+    );
+    assert!(Settings::from_env_vars([("CLIR_TRIM".to_string(), "yes".to_string())]).is_err());
+    // Values are taken as is, rather than read as toml
+    let env = Settings::from_env_vars([("CLIR_TITLE".to_string(), "a\"b\nwidth = 3".to_string())])
+        .unwrap();
+    assert_eq!(env.title.as_deref(), Some("a\"b\nwidth = 3"));
+    assert_eq!(env.width, None);
+
+    assert_eq!(Settings::parse(&merged.to_toml()).unwrap(), merged);
+}
+
+/// Serves a few fixed responses on a local port, returning the base url
+fn serve_fetch_test(png: Vec<u8>) -> String {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", server.server_addr().to_ip().unwrap());
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let header = |name: &str, value: &str| {
+                tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+            };
+            let has_header = |name: &str, value: &str| {
+                request.headers().iter().any(|h| {
+                    h.field.as_str().as_str().eq_ignore_ascii_case(name)
+                        && h.value.as_str() == value
+                })
+            };
+            let response = match request.url() {
+                "/image" => tiny_http::Response::from_data(png.clone())
+                    .with_header(header("Content-Type", "image/png")),
+                "/page" => tiny_http::Response::from_data(b"<!DOCTYPE html><html></html>".to_vec())
+                    .with_header(header("Content-Type", "text/html; charset=utf-8")),
+                "/untyped-page" => {
+                    tiny_http::Response::from_data(b"\n  <html><body></body></html>".to_vec())
+                }
+                "/redirect" => tiny_http::Response::from_data(Vec::new())
+                    .with_status_code(302)
+                    .with_header(header("Location", "/image")),
+                "/private"
+                    if has_header("Authorization", "Bearer token")
+                        && has_header("User-Agent", "test-agent") =>
+                {
+                    tiny_http::Response::from_data(png.clone())
+                }
+                _ => tiny_http::Response::from_data(Vec::new()).with_status_code(404),
+            };
+            let _ = request.respond(response);
+        }
+    });
+    base
+}
+
+#[test]
+fn fetch_test() {
+    let png = fs::read("./test_resource/test_image_2.png").unwrap();
+    let base = serve_fetch_test(png.clone());
+    let settings = FetchSettings::default();
+
+    assert!(fetch::is_url(&format!("{}/image", base)));
+    assert!(fetch::is_url("data:image/png;base64,AAAA"));
+    assert!(fetch::is_url("file:///tmp/image.png"));
+    assert!(!fetch::is_url("./test_resource/test_image_2.png"));
+    assert!(!fetch::is_url("C:\\images\\a.png"));
+
+    assert_eq!(
+        fetch::fetch(&format!("{}/image", base), &settings).unwrap(),
+        png
+    );
+    assert_eq!(
+        fetch::fetch(&format!("{}/redirect", base), &settings).unwrap(),
+        png
+    );
+    for page in ["page", "untyped-page"] {
+        let err = fetch::fetch(&format!("{}/{}", base, page), &settings).unwrap_err();
+        assert!(err.to_string().contains("web page"), "{}", err);
+    }
+    assert!(fetch::fetch(&format!("{}/missing", base), &settings).is_err());
+
+    let small = FetchSettings {
+        max_size: 100,
+        ..Default::default()
+    };
+    let err = fetch::fetch(&format!("{}/image", base), &small).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<LimitError>(),
+        Some(&LimitError::Download { max: 100 })
+    );
+
+    let private = format!("{}/private", base);
+    assert!(fetch::fetch(&private, &settings).is_err());
+    let authorized = FetchSettings {
+        user_agent: "test-agent".to_string(),
+        headers: vec![fetch::parse_header("Authorization: Bearer token").unwrap()],
+        ..Default::default()
+    };
+    assert_eq!(fetch::fetch(&private, &authorized).unwrap(), png);
+
+    let data = format!("data:image/png;base64,{}", STANDARD.encode(&png));
+    assert_eq!(fetch::fetch(&data, &settings).unwrap(), png);
+    assert!(fetch::fetch("data:text/html,<html></html>", &settings).is_err());
+
+    let path = fs::canonicalize("./test_resource/test_image_2.png").unwrap();
+    let file = url::Url::from_file_path(path).unwrap();
+    assert_eq!(fetch::fetch(file.as_str(), &settings).unwrap(), png);
+
+    assert_eq!(fetch::parse_size("20M").unwrap(), 20 * 1024 * 1024);
+    assert_eq!(fetch::parse_size("512").unwrap(), 512);
+    assert!(fetch::parse_size("lots").is_err());
+    assert!(fetch::parse_header("no colon").is_err());
+}
+
+/// Body of a response which sends its first bytes, then sends the rest one byte at a time after each delay
+struct SlowBody {
+    data: Vec<u8>,
+    sent: usize,
+    first: usize,
+    delay: std::time::Duration,
+}
+
+impl std::io::Read for SlowBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = if self.sent < self.first {
+            self.first - self.sent
+        } else {
+            std::thread::sleep(self.delay);
+            1
+        };
+        let len = len.min(buf.len()).min(self.data.len() - self.sent);
+        buf[..len].copy_from_slice(&self.data[self.sent..self.sent + len]);
+        self.sent += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn fetch_timeout_test() {
+    use std::time::{Duration, Instant};
+
+    let png = fs::read("./test_resource/test_image_2.png").unwrap();
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", server.server_addr().to_ip().unwrap());
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            // Stalls for a long time after the first half, or drips a byte every 50ms
+            let delay = match request.url() {
+                "/stall" => Duration::from_secs(5),
+                _ => Duration::from_millis(50),
+            };
+            let body = SlowBody {
+                data: png.clone(),
+                sent: 0,
+                first: png.len() / 2,
+                delay,
+            };
+            let len = png.len();
+            std::thread::spawn(move || {
+                let response =
+                    tiny_http::Response::new(200.into(), Vec::new(), body, Some(len), None);
+                let _ = request.respond(response);
+            });
+        }
+    });
+
+    // The read timeout fails a stalled download before the total timeout
+    let settings = FetchSettings {
+        read_timeout: Duration::from_millis(300),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    assert!(fetch::fetch(&format!("{}/stall", base), &settings).is_err());
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    // The total timeout fails a download which keeps sending slowly
+    let settings = FetchSettings {
+        timeout: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let err = fetch::fetch(&format!("{}/drip", base), &settings).unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(3), "{:?}", err);
+}
+
+#[test]
+fn cache_test() {
+    let dir = std::path::PathBuf::from("./test-outputs/cache/");
+    let _ = fs::remove_dir_all(&dir);
+    let png = fs::read("./test_resource/test_image_2.png").unwrap();
+
+    // Serves the image with an ETag, counting the responses which send the full image
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/logo", server.server_addr().to_ip().unwrap());
+    let downloads = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let served = downloads.clone();
+    let data = png.clone();
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let revalidated = request.headers().iter().any(|h| {
+                h.field
+                    .as_str()
+                    .as_str()
+                    .eq_ignore_ascii_case("If-None-Match")
+                    && h.value.as_str() == "\"v1\""
+            });
+            let response = if revalidated {
+                tiny_http::Response::from_data(Vec::new()).with_status_code(304)
+            } else {
+                served.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tiny_http::Response::from_data(data.clone())
+            };
+            let etag = tiny_http::Header::from_bytes(&b"ETag"[..], &b"\"v1\""[..]).unwrap();
+            let _ = request.respond(response.with_header(etag));
+        }
+    });
+
+    let cache = Cache::new(dir.clone(), 2 * png.len() as u64 + 1024);
+    let settings = FetchSettings {
+        cache: Some(cache.clone()),
+        ..Default::default()
+    };
+    assert_eq!(fetch::fetch(&url, &settings).unwrap(), png);
+    assert_eq!(fetch::fetch(&url, &settings).unwrap(), png);
+    assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1);
+    let entry = cache.get_fetched(&url).unwrap();
+    assert_eq!(entry.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(entry.bytes, png);
+
+    let key = cache::hash_key(&[b"source", b"settings"]);
+    assert_ne!(key, cache::hash_key(&[b"sourc", b"esettings"]));
+    assert_eq!(cache.get_output(&key), None);
+    cache.put_output(&key, b"art").unwrap();
+    assert_eq!(cache.get_output(&key).as_deref(), Some(&b"art"[..]));
+
+    // The output is older than the download, but reading it marks it as used, so the download is evicted first.
+    // The times are set explicitly, as filesystems with coarse times could give both the same time.
+    let set_used = |path: &std::path::Path, secs: u64| {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .unwrap();
+    };
+    for entry in fs::read_dir(dir.join("fetch")).unwrap() {
+        set_used(&entry.unwrap().path(), 2000);
+    }
+    set_used(&dir.join("output").join(&key), 1000);
+    cache.get_output(&key).unwrap();
+    cache.put_output("large", &png).unwrap();
+    cache.put_output("larger", &png).unwrap();
+    assert!(cache.evict().unwrap() > 0);
+    assert!(cache.size() <= 2 * png.len() as u64 + 1024);
+    assert!(cache.get_fetched(&url).is_none());
+    assert!(cache.get_output(&key).is_some());
+
+    cache.clear().unwrap();
+    assert_eq!(cache.size(), 0);
+    assert!(cache.get_output(&key).is_none());
+}
+
+#[test]
+fn decode_limits_test() {
+    let mut bytes = Vec::new();
+    RgbaImage::new(300, 200)
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    let limit_error = |limits: DecodeLimits| {
+        limits
+            .decode(&bytes, image::ImageFormat::Png)
+            .unwrap_err()
+            .downcast::<LimitError>()
+            .unwrap()
+    };
+
+    assert!(DecodeLimits::default()
+        .decode(&bytes, image::ImageFormat::Png)
+        .is_ok());
+    assert_eq!(
+        limit_error(DecodeLimits {
+            max_width: 100,
+            max_height: 1000,
+            ..Default::default()
+        }),
+        LimitError::Dimensions {
+            width: 300,
+            height: 200,
+            max_width: 100,
+            max_height: 1000
+        }
+    );
+    assert!(matches!(
+        limit_error(DecodeLimits {
+            max_bytes: 1000,
+            ..Default::default()
+        }),
+        LimitError::Memory { .. }
+    ));
+
+    let limits = DecodeLimits {
+        max_bytes: 300 * 200 * 16,
+        ..Default::default()
+    };
+    assert!(limits.check_f32(300, 200).is_ok());
+    assert_eq!(
+        limits.check_f32(301, 200),
+        Err(LimitError::Memory {
+            needed: Some(301 * 200 * 16),
+            max: 300 * 200 * 16
+        })
+    );
+
+    assert_eq!(limits::parse_dimensions("8000x6000").unwrap(), (8000, 6000));
+    assert_eq!(limits::parse_dimensions("512").unwrap(), (512, 512));
+    assert!(limits::parse_dimensions("big").is_err());
+}
+
+#[test]
+fn fit_modes_test() {
+    // Left half red, right half blue
+    let img = image::DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 20, |x, _| {
+        if x < 20 {
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([0, 0, 255, 255])
+        }
+    }));
+    let nearest = image::imageops::FilterType::Nearest;
+    let fit = |mode, gravity, w, h| {
+        fit::fit_image(&img, w, h, mode, gravity, Color::WHITE, 1.0, nearest).into_rgba8()
+    };
+
+    let filled = fit(FitMode::Fill, Gravity::CENTER, 10, 30);
+    assert_eq!(filled.dimensions(), (10, 30));
+
+    // Scaled to 20x10, with 5 rows of padding above & below
+    let contained = fit(FitMode::Contain, Gravity::CENTER, 20, 20);
+    assert_eq!(contained.dimensions(), (20, 20));
+    assert_eq!(*contained.get_pixel(10, 2), Rgba([255, 255, 255, 255]));
+    assert_eq!(*contained.get_pixel(2, 10), Rgba([255, 0, 0, 255]));
+    assert_eq!(*contained.get_pixel(17, 10), Rgba([0, 0, 255, 255]));
+    assert_eq!(*contained.get_pixel(10, 17), Rgba([255, 255, 255, 255]));
+
+    let left = fit(FitMode::Cover, fit::get_gravity("left"), 10, 20);
+    assert_eq!(left.dimensions(), (10, 20));
+    assert!(left.pixels().all(|p| *p == Rgba([255, 0, 0, 255])));
+    let right = fit(FitMode::Cover, fit::get_gravity("0.9,0.5"), 10, 20);
+    assert!(right.pixels().all(|p| *p == Rgba([0, 0, 255, 255])));
+    let center = fit(FitMode::Cover, Gravity::CENTER, 10, 20);
+    assert_eq!(*center.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    assert_eq!(*center.get_pixel(9, 0), Rgba([0, 0, 255, 255]));
+
+    assert_eq!(fit::get_gravity("top-right"), Gravity { x: 1.0, y: 0.0 });
+    assert_eq!(fit::get_gravity("nonsense"), Gravity::CENTER);
+    assert_eq!(fit::get_fit_mode("cover"), Some(FitMode::Cover));
+    assert_eq!(fit::get_fit_mode("zoom"), None);
+
+    assert_eq!(Color::parse("#fff"), Some(Color::WHITE));
+    assert_eq!(Color::parse("000000"), Some(Color::BLACK));
+    assert_eq!(Color::parse("#ff000000").map(|c| c.a), Some(0.0));
+    assert_eq!(Color::parse("#12345"), None);
+}
+
+#[test]
+fn transform_test() {
+    // 4x2 image, with the top left pixel red
+    let img = image::DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 2, |x, y| {
+        if (x, y) == (0, 0) {
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    }));
+    let red = Rgba([255, 0, 0, 255]);
+
+    let rotated = Transform::new(None, 90, None)
+        .unwrap()
+        .apply(img.clone())
+        .unwrap()
+        .into_rgba8();
+    assert_eq!(rotated.dimensions(), (2, 4));
+    assert_eq!(*rotated.get_pixel(1, 0), red);
+
+    let flipped = Transform::new(None, 0, transform::get_flip("h"))
+        .unwrap()
+        .apply(img.clone())
+        .unwrap()
+        .into_rgba8();
+    assert_eq!(*flipped.get_pixel(3, 0), red);
+    assert_eq!(transform::get_flip("v"), Some(Flip::Vertical));
+    assert!(Transform::new(None, 45, None).is_err());
+
+    let crop = Crop::parse("0,0,50%,1").unwrap();
+    assert_eq!(crop.width, CropValue::Percent(50.0));
+    assert_eq!(crop.rect(4, 2).unwrap(), (0, 0, 2, 1));
+    // The crop is cut to the image
+    assert_eq!(
+        Crop::parse("2,1,10,10").unwrap().rect(4, 2).unwrap(),
+        (2, 1, 2, 1)
+    );
+    assert!(Crop::parse("4,0,1,1").unwrap().rect(4, 2).is_err());
+    assert!(Crop::parse("1,2,3").is_err());
+    assert!(Crop::parse("a,b,c,d").is_err());
+
+    // Cropped before rotating
+    let cropped = Transform::new(Some(crop), 180, None)
+        .unwrap()
+        .apply(img.clone())
+        .unwrap()
+        .into_rgba8();
+    assert_eq!(cropped.dimensions(), (2, 1));
+    assert_eq!(*cropped.get_pixel(1, 0), red);
+
+    // A png with an EXIF orientation of 6 (rotated 90 degrees clockwise) is decoded upright
+    let exif = vec![
+        b'M', b'M', 0, 42, 0, 0, 0, 8, // header, offset of the first IFD
+        0, 1, // number of entries
+        0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // orientation, SHORT, count 1, value 6
+        0, 0, 0, 0, // no next IFD
+    ];
+    let mut bytes = Vec::new();
+    let mut encoder = image::codecs::png::PngEncoder::new(&mut bytes);
+    image::ImageEncoder::set_exif_metadata(&mut encoder, exif).unwrap();
+    image::ImageEncoder::write_image(
+        encoder,
+        img.to_rgba8().as_raw(),
+        4,
+        2,
+        image::ExtendedColorType::Rgba8,
+    )
+    .unwrap();
+    let decoded = utils::decode_image(&bytes).unwrap().into_rgba8();
+    assert_eq!(decoded.dimensions(), (2, 4));
+    assert_eq!(*decoded.get_pixel(1, 0), red);
+}
+
+#[test]
+fn trim_test() {
+    // Content from (3,2) to (5,4) on a transparent 10x8 image, with a faint pixel at (8,7)
+    let mut img = RgbaImage::new(10, 8);
+    for x in 3..=5 {
+        for y in 2..=4 {
+            img.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        }
+    }
+    img.put_pixel(8, 7, Rgba([255, 255, 255, 10]));
+    let img = image::DynamicImage::ImageRgba8(img);
+
+    let trim = Trim {
+        tolerance: Trim::DEFAULT_TOLERANCE,
+        padding: 0,
+    };
+    assert_eq!(trim.bounds(&img), Some((3, 2, 3, 3)));
+    let strict = Trim {
+        tolerance: 0.0,
+        ..trim
+    };
+    assert_eq!(strict.bounds(&img), Some((3, 2, 6, 6)));
+    // Padding is kept inside the image
+    let padded = Trim { padding: 3, ..trim };
+    assert_eq!(padded.bounds(&img), Some((0, 0, 9, 8)));
+
+    let trimmed = Transform {
+        trim: Some(trim),
+        ..Default::default()
+    }
+    .apply(img)
+    .unwrap();
+    assert_eq!((trimmed.width(), trimmed.height()), (3, 3));
+
+    // Solid color margins are found from the corner, and an image of a single color is kept as is
+    let solid = image::DynamicImage::ImageRgba8(RgbaImage::from_fn(6, 6, |x, y| {
+        if (x, y) == (4, 1) {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([250, 250, 250, 255])
+        }
+    }));
+    assert_eq!(trim.bounds(&solid), Some((4, 1, 1, 1)));
+    let blank = image::DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+    assert_eq!(trim.bounds(&blank), None);
+}
+
+#[test]
+fn cell_aspect_test() {
+    assert_eq!(terminal::parse_cell_aspect("0.45"), Some(0.45));
+    assert_eq!(terminal::parse_cell_aspect("9:20"), Some(0.45));
+    assert_eq!(terminal::parse_cell_aspect(" 1 / 2 "), Some(0.5));
+    assert_eq!(terminal::parse_cell_aspect("0"), None);
+    assert_eq!(terminal::parse_cell_aspect("1:0"), None);
+    assert_eq!(terminal::parse_cell_aspect("wide"), None);
+
+    // 80x24 cells in 720x528 pixels, each cell is 9x22
+    assert_eq!(
+        terminal::aspect_from_pixels(80, 24, 720, 528),
+        Some(9.0 / 22.0)
+    );
+    assert_eq!(terminal::aspect_from_pixels(80, 24, 0, 0), None);
+    assert_eq!(
+        terminal::cell_size_from_pixels(80, 24, 720, 528),
+        Some((9.0, 22.0))
+    );
+
+    // Answers to CSI 16 t & CSI 14 t, with unrelated input around them
+    let reports = terminal::parse_size_reports(b"a\x1b[6;20;9t\x1b[?1;2c\x1b[4;480;720tb");
+    assert_eq!(
+        reports,
+        SizeReports {
+            cell: Some((9, 20)),
+            text_area: Some((720, 480)),
+        }
+    );
+    assert_eq!(
+        terminal::parse_size_reports(b"\x1b[8;24;80t"),
+        SizeReports::default()
+    );
+    // The answer to the device attributes request ends the reports
+    assert!(terminal::has_device_attributes(b"\x1b[6;20;9t\x1b[?62;22c"));
+    assert!(terminal::has_device_attributes(b"\x1b[?1;2c"));
+    assert!(!terminal::has_device_attributes(b"\x1b[6;20;9t\x1b[?62;2"));
+    assert!(!terminal::has_device_attributes(b"\x1b[4;480;720t"));
+
+    // Pixels displayed twice as wide as tall need half the columns for contain to keep the image square on screen
+    let img =
+        image::DynamicImage::ImageRgba8(RgbaImage::from_pixel(20, 20, Rgba([255, 0, 0, 255])));
+    let contained = fit::fit_image(
+        &img,
+        40,
+        40,
+        FitMode::Contain,
+        Gravity::CENTER,
+        Color::TRANSPARENT,
+        2.0,
+        image::imageops::FilterType::Nearest,
+    )
+    .into_rgba8();
+    assert_eq!(contained.get_pixel(5, 20)[3], 0);
+    assert_eq!(*contained.get_pixel(20, 20), Rgba([255, 0, 0, 255]));
+    assert_eq!(*contained.get_pixel(20, 0), Rgba([255, 0, 0, 255]));
+    assert_eq!(contained.get_pixel(35, 20)[3], 0);
+}
+
+#[test]
+fn layout_test() {
+    assert_eq!(
+        Spacing::parse("1,2").unwrap(),
+        Spacing {
+            top: 1,
+            right: 2,
+            bottom: 1,
+            left: 2
+        }
+    );
+    assert_eq!(Spacing::parse("1,2,3,4").unwrap().horizontal(), 6);
+    assert!(Spacing::parse("1,2,3").is_err());
+    assert_eq!(layout::get_align("center"), Some(Align::Center));
+    assert_eq!(layout::get_vertical_align("middle"), None);
+
+    // Escape sequences take no columns, emoji take two
+    assert_eq!(layout::display_width("\x1b[38;2;1;2;3mab\x1b[0m"), 2);
+    assert_eq!(layout::display_width("\u{1f7e5}\u{1f7e6}"), 4);
+    assert_eq!(
+        layout::display_width("\x1b]8;;https://example.com\x1b\\a\x1b]8;;\x07"),
+        1
+    );
+
+    let centered = Layout {
+        align: Align::Center,
+        vertical_align: VerticalAlign::Center,
+        columns: 10,
+        rows: 7,
+        ..Default::default()
+    };
+    // 6 rows are filled, with the last one left for the prompt, so that the position does not depend on the cursor
+    let output = centered.apply("ab\nabcd\n");
+    assert_eq!(output, "\n\n   ab  \n   abcd\n\n\n");
+    assert_eq!(output.lines().count(), centered.rows - 1);
+    let bottom = Layout {
+        vertical_align: VerticalAlign::Bottom,
+        margin: Spacing::parse("1,0").unwrap(),
+        ..centered.clone()
+    };
+    let output = bottom.apply("ab\nabcd\n");
+    assert_eq!(output, "\n\n\n   ab  \n   abcd\n\n");
+    assert_eq!(output.lines().count(), bottom.rows - 1);
+
+    let padded = Layout {
+        align: Align::Right,
+        margin: Spacing::parse("0,1").unwrap(),
+        padding: Spacing::parse("1,1,0,1").unwrap(),
+        fill: Some(RGBColorU8 { r: 255, g: 0, b: 0 }),
+        columns: 8,
+        ..Default::default()
+    };
+    assert_eq!(padded.reserved(), (4, 1));
+    let red = |n| format!("\x1b[48;2;255;0;0m{}\x1b[0m", " ".repeat(n));
+    assert_eq!(
+        padded.apply("ab\n"),
+        format!("   {}\n   {}ab{}\n", red(4), red(1), red(1))
+    );
+    assert!(Layout::default().is_empty());
+}
+
+#[test]
+fn border_test() {
+    let framed = Layout {
+        border: Some(BorderStyle::Rounded),
+        title: Some("cat.png".to_string()),
+        caption: Some("40x20".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(framed.reserved(), (2, 3));
+    assert_eq!(
+        framed.apply("abcdefghijk\nabc\n"),
+        "╭─ cat.png ─╮\n│abcdefghijk│\n│abc        │\n╰───────────╯\n    40x20\n"
+    );
+
+    // Titles are cut by their width in columns, so wide characters are not split
+    let narrow = Layout {
+        border: Some(BorderStyle::Double),
+        title: Some("画像ファイル".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        narrow.apply("abcdefgh\n"),
+        "╔═ 画… ══╗\n║abcdefgh║\n╚════════╝\n"
+    );
+    assert_eq!(layout::truncate_to_width("a\x1bb\n", 5), "ab");
+    assert_eq!(layout::truncate_to_width("猫猫", 4), "猫猫");
+    assert_eq!(layout::truncate_to_width("猫猫", 3), "猫…");
+    assert_eq!(layout::get_border_style("none"), None);
+
+    let colored = Layout {
+        border: Some(BorderStyle::Single),
+        border_color: Some(RGBColorU8 { r: 1, g: 2, b: 3 }),
+        ..Default::default()
+    };
+    assert_eq!(
+        colored.apply("a\n"),
+        "\x1b[38;2;1;2;3m┌─┐\x1b[0m\n\x1b[38;2;1;2;3m│\x1b[0ma\x1b[38;2;1;2;3m│\x1b[0m\n\x1b[38;2;1;2;3m└─┘\x1b[0m\n"
+    );
+
+    // The fore color covers 6 of the 8 pixels in each cell
+    let red = Color {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    let cell = ComputedCell {
+        fore: red,
+        back: Color::WHITE,
+        bitmask: 0b0011_1111,
+    };
+    let grid = ComputedCellGrid::new(vec![cell; 4], 2, 2).unwrap();
+    assert_eq!(grid.dominant_color(), Some(red));
+    let clear = ComputedCell {
+        fore: Color::TRANSPARENT,
+        back: Color::TRANSPARENT,
+        bitmask: 0,
+    };
+    let grid = ComputedCellGrid::new(vec![clear], 1, 1).unwrap();
+    assert_eq!(grid.dominant_color(), None);
+
+    assert_eq!(batch::source_name("icons/logo.png"), "logo.png");
+    assert_eq!(
+        batch::source_name("https://example.com/a/cat.jpg?size=2"),
+        "cat.jpg"
+    );
+}
+
+#[test]
+fn multi_writer_test() {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    /// Keeps what was written where the test can read it
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    /// Fails like stdout piped into a closed `head`
+    struct ClosedPipe;
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let file = Rc::new(RefCell::new(Vec::new()));
+    let mut out = MultiWriter::new();
+    out.push("stdout", Box::new(ClosedPipe));
+    out.push("file", Box::new(Shared(file.clone())));
+    out.write_all(b"first ").unwrap();
+    out.write_all(b"second").unwrap();
+    assert_eq!(*file.borrow(), b"first second");
+    let errors = out.take_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "stdout");
+    assert_eq!(errors[0].1.kind(), std::io::ErrorKind::BrokenPipe);
+
+    // Fails once no writer is left
+    let mut out = MultiWriter::new();
+    out.push("stdout", Box::new(ClosedPipe));
+    assert!(out.write_all(b"x").is_err());
+}
+
+#[test]
+fn capture_writer_test() {
+    /// Keeps each write separately, to check that rows are written as they are rendered
+    struct RecordingWriter(Vec<Vec<u8>>);
+    impl std::io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let img = ImageReader::open("./test_resource/test_image.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let computed = CellGrid::try_from(&img.to_rgba32f())
+        .unwrap()
+        .compute(false);
+    let (expected, _) = AsciiImageRenderer::render(&computed, true, None, 0.25);
+
+    // The output is still streamed row by row while it is captured for the cache
+    let mut out = RecordingWriter(Vec::new());
+    let mut capture = CaptureWriter::new(&mut out, cache::MAX_OUTPUT_SIZE);
+    AsciiImageRenderer::render_to(&mut capture, &computed, true, None, 0.25).unwrap();
+    assert_eq!(capture.into_captured(), Some(expected.clone().into_bytes()));
+    assert_eq!(out.0.len(), computed.height());
+    assert_eq!(out.0.concat(), expected.as_bytes());
+
+    // Outputs over the limit are written but not kept
+    let mut out = RecordingWriter(Vec::new());
+    let mut capture = CaptureWriter::new(&mut out, expected.len() - 1);
+    AsciiImageRenderer::render_to(&mut capture, &computed, true, None, 0.25).unwrap();
+    assert_eq!(capture.into_captured(), None);
+    assert_eq!(out.0.concat(), expected.as_bytes());
+}
@@ -0,0 +1,203 @@
+//! Geometric transforms applied to the source image before it is sized & resized: cropping, rotating & flipping.
+
+use anyhow::{anyhow, bail};
+use image::{DynamicImage, GenericImageView, Rgba};
+
+/// Coordinate of a crop, in pixels or as a percentage of the image's width or height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropValue {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl CropValue {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => percent
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|x| x.is_finite() && *x >= 0.0)
+                .map(CropValue::Percent),
+            None => s.parse().ok().map(CropValue::Pixels),
+        }
+    }
+
+    /// Value in pixels, out of the image's total width or height
+    fn pixels(&self, total: u32) -> u32 {
+        match self {
+            CropValue::Pixels(x) => *x,
+            CropValue::Percent(x) => (total as f32 * x / 100.0).round() as u32,
+        }
+    }
+}
+
+/// Rectangle of the image which is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: CropValue,
+    pub y: CropValue,
+    pub width: CropValue,
+    pub height: CropValue,
+}
+
+impl Crop {
+    /// Parses the crop from "x,y,width,height", where each value is in pixels or a percentage, eg. "10%,0,50%,100%"
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "Invalid crop '{}', expected x,y,width,height in pixels or percentages, eg. 0,0,50%,50%",
+                s
+            )
+        };
+        let values = s
+            .split(',')
+            .map(CropValue::parse)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// Rectangle of the crop in pixels, cut to fit inside the image
+    pub fn rect(&self, width: u32, height: u32) -> anyhow::Result<(u32, u32, u32, u32)> {
+        let x = self.x.pixels(width);
+        let y = self.y.pixels(height);
+        if x >= width || y >= height {
+            bail!(
+                "Crop starts at {},{}, outside of the {}x{} image",
+                x,
+                y,
+                width,
+                height
+            );
+        }
+        let w = self.width.pixels(width).min(width - x);
+        let h = self.height.pixels(height).min(height - y);
+        if w == 0 || h == 0 {
+            bail!("Crop is empty");
+        }
+        Ok((x, y, w, h))
+    }
+}
+
+/// Removes the margins around the content of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trim {
+    /// Pixels with alpha up to this fraction, or with every channel within this fraction of the top left corner's color, are margin
+    pub tolerance: f32,
+    /// Pixels of margin kept around the content
+    pub padding: u32,
+}
+
+impl Trim {
+    pub const DEFAULT_TOLERANCE: f32 = 0.1;
+
+    fn is_margin(&self, pixel: Rgba<u8>, corner: Rgba<u8>) -> bool {
+        let t = (self.tolerance.clamp(0.0, 1.0) * 255.0).round() as u8;
+        pixel[3] <= t || (0..4).all(|ch| pixel[ch].abs_diff(corner[ch]) <= t)
+    }
+
+    /// Bounding box of the content as x, y, width, height, with the padding added. None when the image is all margin.
+    pub fn bounds(&self, img: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let corner = img.get_pixel(0, 0);
+        let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in img.pixels() {
+            if !self.is_margin(pixel, corner) {
+                x0 = x0.min(x);
+                y0 = y0.min(y);
+                x1 = x1.max(x);
+                y1 = y1.max(y);
+            }
+        }
+        if x0 > x1 {
+            return None;
+        }
+        let x0 = x0.saturating_sub(self.padding);
+        let y0 = y0.saturating_sub(self.padding);
+        let x1 = x1.saturating_add(self.padding).min(width - 1);
+        let y1 = y1.saturating_add(self.padding).min(height - 1);
+        Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flip {
+    /// Mirrors left & right
+    Horizontal,
+    /// Mirrors top & bottom
+    Vertical,
+}
+
+pub fn get_flip(name: &str) -> Option<Flip> {
+    match name {
+        "h" | "horizontal" => Some(Flip::Horizontal),
+        "v" | "vertical" => Some(Flip::Vertical),
+        _ => None,
+    }
+}
+
+/// Transforms applied in order: crop, rotate, flip then trim. The crop is in the coordinates of the upright source image.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Transform {
+    pub crop: Option<Crop>,
+    /// Clockwise rotation in degrees, one of 0, 90, 180 & 270
+    pub rotate: u32,
+    pub flip: Option<Flip>,
+    pub trim: Option<Trim>,
+}
+
+impl Transform {
+    pub fn new(crop: Option<Crop>, rotate: u32, flip: Option<Flip>) -> anyhow::Result<Self> {
+        if !matches!(rotate, 0 | 90 | 180 | 270) {
+            bail!(
+                "Invalid rotation of {} degrees, expected 90, 180 or 270",
+                rotate
+            );
+        }
+        Ok(Self {
+            crop,
+            rotate,
+            flip,
+            trim: None,
+        })
+    }
+
+    pub fn apply(&self, img: DynamicImage) -> anyhow::Result<DynamicImage> {
+        let img = match &self.crop {
+            Some(crop) => {
+                let (x, y, w, h) = crop.rect(img.width(), img.height())?;
+                img.crop_imm(x, y, w, h)
+            }
+            None => img,
+        };
+        let img = match self.rotate {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+        let img = match self.flip {
+            Some(Flip::Horizontal) => img.fliph(),
+            Some(Flip::Vertical) => img.flipv(),
+            None => img,
+        };
+        // Images which are all margin are kept as they are
+        Ok(match self.trim.and_then(|trim| trim.bounds(&img)) {
+            Some((x, y, w, h)) => img.crop_imm(x, y, w, h),
+            None => img,
+        })
+    }
+}